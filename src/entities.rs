@@ -1,22 +1,60 @@
 use std::cmp::Ordering;
 
+use serde::{Deserialize, Serialize};
 use strum::IntoEnumIterator;
 use strum_macros::{EnumDiscriminants, EnumIter};
-use OrderEvent::{CustomerAdded, ItemAdded, ItemDeleted, OrderDelivered, OrderDeliveryFailed, OrderDetailsAdded, OrderPayed, OrderSent};
+use OrderEvent::{
+    ActionCompleted, AddressesResolved, CustomerAdded, CustomerResponded, DeliveryEscalated, ItemAdded, ItemDeleted, OrderDelivered,
+    OrderDeliveryFailed, OrderDetailsAdded, OrderExpired, OrderPayed, OrderRefunded, OrderReshipped, OrderSent,
+    PaymentAuthorizationExpired, PaymentAuthorized, PaymentCaptured, PaymentDeclined, PaymentSettled, ShipmentDelivered,
+    ShipmentDeliveryFailed, ShipmentSent,
+};
 
 pub type OrderId = String;
 pub type OrderItemId = String;
 pub type CustomerId = String;
+pub type ShipmentId = String;
+pub type AuthorizationId = String;
 
-#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Default, Hash)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Default, Hash, Serialize, Deserialize)]
 pub enum PaymentType {
     #[default]
     Visa,
     Mastercard,
     Americanexpress,
+    MobilePay,
+    KlarnaInvoice,
+    BankTransfer,
+    GiftCard,
+    StoreCredit,
 }
 
-#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Default, Hash)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default, Hash)]
+pub enum Settlement {
+    #[default]
+    Instant,
+    Deferred,
+}
+
+impl PaymentType {
+    /// When the money actually reaches us. Klarna settles later but guarantees the amount; a bank transfer
+    /// is only money once it has arrived.
+    pub const fn settlement(self) -> Settlement {
+        match self {
+            Self::Visa | Self::Mastercard | Self::Americanexpress | Self::MobilePay | Self::GiftCard | Self::StoreCredit => {
+                Settlement::Instant
+            }
+            Self::KlarnaInvoice | Self::BankTransfer => Settlement::Deferred,
+        }
+    }
+
+    /// Whether an order paid this way must wait for `PaymentSettled` before it can be shipped.
+    pub const fn holds_shipping(self) -> bool {
+        matches!(self, Self::BankTransfer)
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Default, Hash, Serialize, Deserialize)]
 pub enum DeliveryType {
     #[default]
     Gls,
@@ -24,7 +62,7 @@ pub enum DeliveryType {
     Bring,
 }
 
-#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, EnumDiscriminants)]
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, EnumDiscriminants, Serialize, Deserialize)]
 #[strum_discriminants(derive(EnumIter, Hash))]
 pub enum OrderEvent {
     ItemAdded {
@@ -46,7 +84,7 @@ pub enum OrderEvent {
     OrderDetailsAdded {
         order_id: OrderId,
         delivery_type: DeliveryType,
-        delivery_address: Option<Address>,
+        delivery_address: Option<Pii<Address>>,
         customer: CustomerId,
         time: u32,
     },
@@ -63,32 +101,206 @@ pub enum OrderEvent {
         reason: Reason,
         time: u32,
     },
+    /// Legacy: kept so existing order streams still replay. New customers are registered on their own
+    /// stream with `CustomerEvent::CustomerRegistered`, and this event no longer moves the order state.
     CustomerAdded {
         customer: CustomerId,
-        first_name: String,
-        last_name: String,
-        address: Address,
+        first_name: Pii<String>,
+        last_name: Pii<String>,
+        address: Pii<Address>,
+        time: u32,
+    },
+    ShipmentSent {
+        order_id: OrderId,
+        shipment_id: ShipmentId,
+        delivery_type: DeliveryType,
+        tracking_number: String,
+        items: Vec<OrderItemId>,
+        time: u32,
+    },
+    ShipmentDelivered {
+        order_id: OrderId,
+        shipment_id: ShipmentId,
+        time: u32,
+    },
+    ShipmentDeliveryFailed {
+        order_id: OrderId,
+        shipment_id: ShipmentId,
+        reason: Reason,
+        time: u32,
+    },
+    PaymentAuthorized {
+        order_id: OrderId,
+        authorization_id: AuthorizationId,
+        payment_type: PaymentType,
+        amount: u32,
+        expires_at: u32,
+        time: u32,
+    },
+    PaymentCaptured {
+        order_id: OrderId,
+        authorization_id: AuthorizationId,
+        amount: u32,
         time: u32,
     },
+    PaymentDeclined {
+        order_id: OrderId,
+        payment_type: PaymentType,
+        reason: String,
+        time: u32,
+    },
+    PaymentAuthorizationExpired {
+        order_id: OrderId,
+        authorization_id: AuthorizationId,
+        time: u32,
+    },
+    PaymentSettled {
+        order_id: OrderId,
+        amount: u32,
+        time: u32,
+    },
+    AddressesResolved {
+        order_id: OrderId,
+        shipping_address: Pii<Address>,
+        billing_address: Pii<Address>,
+        time: u32,
+    },
+    /// The order was left unpaid for too long; see `infra::timer`.
+    OrderExpired {
+        order_id: OrderId,
+        time: u32,
+    },
+    /// A handler has carried out the order's pending action; see `infra::process`.
+    ActionCompleted {
+        order_id: OrderId,
+        action: Action,
+        time: u32,
+    },
+    /// The customer's answer after being contacted about a failed delivery; see `infra::saga`.
+    CustomerResponded {
+        order_id: OrderId,
+        resolution: DeliveryResolution,
+        time: u32,
+    },
+    /// A failed delivery is sent again, to `address`.
+    OrderReshipped {
+        order_id: OrderId,
+        address: Pii<Address>,
+        time: u32,
+    },
+    OrderRefunded {
+        order_id: OrderId,
+        amount: u32,
+        time: u32,
+    },
+    /// A failed delivery could not be resolved with the customer and is handed to a person.
+    DeliveryEscalated {
+        order_id: OrderId,
+        reason: String,
+        time: u32,
+    },
+}
+
+impl OrderEvent {
+    /// When the event happened.
+    pub const fn time(&self) -> u32 {
+        match self {
+            ItemAdded { time, .. }
+            | ItemDeleted { time, .. }
+            | OrderPayed { time, .. }
+            | OrderDetailsAdded { time, .. }
+            | OrderSent { time, .. }
+            | OrderDelivered { time, .. }
+            | OrderDeliveryFailed { time, .. }
+            | CustomerAdded { time, .. }
+            | ShipmentSent { time, .. }
+            | ShipmentDelivered { time, .. }
+            | ShipmentDeliveryFailed { time, .. }
+            | PaymentAuthorized { time, .. }
+            | PaymentCaptured { time, .. }
+            | PaymentDeclined { time, .. }
+            | PaymentAuthorizationExpired { time, .. }
+            | PaymentSettled { time, .. }
+            | AddressesResolved { time, .. }
+            | OrderExpired { time, .. }
+            | ActionCompleted { time, .. }
+            | CustomerResponded { time, .. }
+            | OrderReshipped { time, .. }
+            | OrderRefunded { time, .. }
+            | DeliveryEscalated { time, .. } => *time,
+        }
+    }
 }
 
 #[allow(clippy::derive_ord_xor_partial_ord)]
 impl Ord for OrderEvent {
     fn cmp(&self, other: &Self) -> Ordering {
         println!("ORD");
+        println!("S {} O {}", self.time(), other.time());
+        self.time().cmp(&other.time())
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Default, Hash, Serialize, Deserialize)]
+pub enum AddressUsage {
+    #[default]
+    Shipping,
+    Billing,
+}
+
+/// Events of the customer stream. Customers live in their own aggregate; orders only refer to them by id.
+#[allow(clippy::enum_variant_names)]
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Serialize, Deserialize)]
+pub enum CustomerEvent {
+    CustomerRegistered {
+        customer: CustomerId,
+        first_name: Pii<String>,
+        last_name: Pii<String>,
+        email: Pii<String>,
+        address: Pii<Address>,
+        time: u32,
+    },
+    CustomerAddressChanged {
+        customer: CustomerId,
+        name: String,
+        address: Pii<Address>,
+        time: u32,
+    },
+    CustomerAddressRemoved {
+        customer: CustomerId,
+        name: String,
+        time: u32,
+    },
+    CustomerDefaultAddressSet {
+        customer: CustomerId,
+        name: String,
+        usage: AddressUsage,
+        time: u32,
+    },
+    CustomerEmailChanged {
+        customer: CustomerId,
+        email: Pii<String>,
+        time: u32,
+    },
+    CustomerDeleted {
+        customer: CustomerId,
+        time: u32,
+    },
+}
+
+#[allow(clippy::derive_ord_xor_partial_ord)]
+impl Ord for CustomerEvent {
+    fn cmp(&self, other: &Self) -> Ordering {
         let get_time = |event: &Self| -> u32 {
             match event {
-                ItemAdded { time, .. }
-                | ItemDeleted { time, .. }
-                | OrderPayed { time, .. }
-                | OrderDetailsAdded { time, .. }
-                | OrderSent { time, .. }
-                | OrderDelivered { time, .. }
-                | OrderDeliveryFailed { time, .. }
-                | CustomerAdded { time, .. } => *time,
+                Self::CustomerRegistered { time, .. }
+                | Self::CustomerAddressChanged { time, .. }
+                | Self::CustomerAddressRemoved { time, .. }
+                | Self::CustomerDefaultAddressSet { time, .. }
+                | Self::CustomerEmailChanged { time, .. }
+                | Self::CustomerDeleted { time, .. } => *time,
             }
         };
-        println!("S {} O {}", get_time(self), get_time(other));
         get_time(self).cmp(&get_time(other))
     }
 }
@@ -104,9 +316,14 @@ pub enum State {
     Delivered,
     DeliveryFailed,
     Failed,
+    PartiallySent,
+    PartiallyDelivered,
+    Authorized,
+    Expired,
+    Refunded,
 }
 
-#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Default, Hash)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Default, Hash, Serialize, Deserialize)]
 pub enum CountryCode {
     #[default]
     Dk,
@@ -114,51 +331,183 @@ pub enum CountryCode {
     De,
 }
 
-#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Default, Hash)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Default, Hash, Serialize, Deserialize)]
 pub enum ReasonCode {
     #[default]
     PackageLost,
     WrongAddress,
+    RecipientAbsent,
+    Refused,
+    Damaged,
+    CustomsHold,
+    AddressUnreachable,
+    WeatherDelay,
+    Other,
 }
 
-#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Default, Hash)]
+impl ReasonCode {
+    /// Whether the carrier will attempt the delivery again without us doing anything.
+    pub const fn is_retryable(self) -> bool {
+        matches!(self, Self::RecipientAbsent | Self::CustomsHold | Self::WeatherDelay)
+    }
+
+    /// The follow-up action a delivery failure with this code calls for.
+    pub const fn action(self) -> Action {
+        match self {
+            Self::PackageLost | Self::WrongAddress | Self::Refused | Self::AddressUnreachable => Action::ContactCustomer,
+            Self::Damaged => Action::PrepareOrder,
+            Self::RecipientAbsent | Self::WeatherDelay => Action::None,
+            Self::CustomsHold | Self::Other => Action::CheckOrder,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Default, Hash, Serialize, Deserialize)]
 pub struct Reason {
     pub reason_code: ReasonCode,
     pub reason_message: String,
 }
 
-#[derive(Debug, Clone, PartialEq, Eq, EnumIter, Default)]
+/// What a customer wants done about a failed delivery.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Hash, Serialize, Deserialize)]
+pub enum DeliveryResolution {
+    /// Send the order again, to a corrected address.
+    Reship(Pii<Address>),
+    Refund,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, EnumIter, Default, Hash, Serialize, Deserialize)]
 pub enum Action {
     #[default]
     None,
     AddItem,
     DeleteItem,
     Pay,
+    Capture,
     RefundDiff,
+    Refund,
     ContactCustomer,
     PrepareOrder,
     CheckOrder,
 }
 
-#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Default, Hash)]
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Default, Hash, Serialize, Deserialize)]
 pub struct Address {
-    pub street: &'static str,
+    pub street: String,
     pub house_number: i16,
     pub zip: i16,
     pub country: CountryCode,
 }
 
+/// Ciphertext of a personal data field, encrypted with the key of `customer`, the customer it belongs to.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Hash, Serialize, Deserialize)]
+pub struct Sealed {
+    pub customer: CustomerId,
+    pub nonce: Vec<u8>,
+    pub ciphertext: Vec<u8>,
+}
+
+/// A personal data field of an event. Events are written `Plain`, stored `Encrypted`, and come back `Plain`
+/// when loaded, or `Redacted` once the customer's key has been deleted.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Hash, Serialize, Deserialize)]
+pub enum Pii<T> {
+    Plain(T),
+    Encrypted(Sealed),
+    Redacted,
+}
+
+/// The placeholder shown in place of personal data that can no longer be decrypted.
+pub trait Redacted {
+    fn redacted() -> Self;
+}
+
+impl Redacted for String {
+    fn redacted() -> Self {
+        "[redacted]".to_string()
+    }
+}
+
+impl Redacted for Address {
+    fn redacted() -> Self {
+        Self { street: String::redacted(), ..Self::default() }
+    }
+}
+
+impl<T: Redacted + Clone> Pii<T> {
+    /// The plain value, or the redacted placeholder if it is not available in plain text.
+    pub fn reveal(&self) -> T {
+        match self {
+            Self::Plain(value) => value.clone(),
+            Self::Encrypted(_) | Self::Redacted => T::redacted(),
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default, Hash)]
+pub enum ShipmentState {
+    #[default]
+    Sent,
+    Delivered,
+    /// Reported undelivered by `ShipmentDeliveryFailed`. Left out of the order state from then on.
+    Failed,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct Shipment {
+    pub id: ShipmentId,
+    pub delivery_type: DeliveryType,
+    pub tracking_number: String,
+    pub items: Vec<OrderItemId>,
+    pub status: ShipmentState,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct PaymentAuthorization {
+    pub id: AuthorizationId,
+    pub amount: u32,
+    pub expires_at: u32,
+}
+
+/// Money taken on an order, as it was paid, so it can be paid back the same way.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Charge {
+    pub payment_type: PaymentType,
+    /// The authorization the money was captured on, or `None` when it was paid without one (`OrderPayed`).
+    pub authorization_id: Option<AuthorizationId>,
+    pub amount: u32,
+}
+
+/// An action the order is waiting on, with the event that raised it. It stays pending until a later event
+/// completes it, either by doing the work (`OrderPayed` completes `Pay`) or by `ActionCompleted`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PendingAction {
+    pub action: Action,
+    pub origin: OrderEventDiscriminants,
+    pub time: u32,
+}
+
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct Order {
     pub id: OrderId,
     pub status: State,
     pub payment_type: Option<PaymentType>,
+    /// Money taken so far, by every payment and capture, less refunds.
     pub amount: u32,
+    /// The part of `amount` paid with a deferred settlement that has not settled yet.
+    pub unsettled: u32,
     pub delivery_type: Option<DeliveryType>,
     pub items: Vec<OrderItemId>,
     pub address: Option<Address>,
+    pub billing_address: Option<Address>,
     pub customer: Option<CustomerId>,
+    pub shipments: Vec<Shipment>,
+    pub authorization: Option<PaymentAuthorization>,
+    pub charges: Vec<Charge>,
+    /// Why the latest delivery attempt failed, until the order is delivered or sent again.
+    pub delivery_failure: Option<Reason>,
+    /// The most recently raised of `pending_actions`, or `None` when nothing is pending.
     pub action: Action,
+    pub pending_actions: Vec<PendingAction>,
 }
 
 impl Order {
@@ -168,11 +517,73 @@ impl Order {
             status: State::Empty,
             items: vec![],
             address: None,
+            billing_address: None,
             customer: None,
             delivery_type: None,
             amount: 0,
+            unsettled: 0,
             payment_type: None,
+            shipments: vec![],
+            authorization: None,
+            charges: vec![],
+            delivery_failure: None,
             action: Action::None,
+            pending_actions: vec![],
         }
     }
+
+    /// Whether shipping waits for money that has not settled yet. Deferred settlements that don't hold
+    /// shipping, such as Klarna's, are guaranteed and only tracked in `unsettled`.
+    pub fn holds_shipping(&self) -> bool {
+        self.unsettled > 0 && self.payment_type.is_some_and(PaymentType::holds_shipping)
+    }
+}
+
+/// An order associated with a second, different customer. `kept` is the customer the order stays with.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct CustomerConflict {
+    pub order_id: OrderId,
+    pub kept: CustomerId,
+    pub rejected: CustomerId,
+    pub time: u32,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default, Hash)]
+pub enum CustomerConflictMode {
+    #[default]
+    Allow,
+    Warn,
+    Reject,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct AddressBookEntry {
+    pub name: String,
+    pub address: Address,
+    pub default_shipping: bool,
+    pub default_billing: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct Customer {
+    pub id: CustomerId,
+    pub first_name: String,
+    pub last_name: String,
+    pub email: String,
+    pub addresses: Vec<AddressBookEntry>,
+    pub deleted: bool,
+}
+
+impl Customer {
+    pub fn new(id: CustomerId) -> Self {
+        Self { id, ..Self::default() }
+    }
+
+    pub fn default_shipping(&self) -> Option<&Address> {
+        self.addresses.iter().find(|entry| entry.default_shipping).map(|entry| &entry.address)
+    }
+
+    pub fn default_billing(&self) -> Option<&Address> {
+        self.addresses.iter().find(|entry| entry.default_billing).map(|entry| &entry.address)
+    }
 }
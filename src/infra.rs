@@ -1 +1,20 @@
-
+pub mod carrier;
+pub mod customer_history;
+pub mod fulfilment;
+pub mod json_file;
+pub mod keystore;
+pub mod order_list;
+pub mod outbox;
+pub mod payment;
+pub mod process;
+pub mod projection;
+pub mod revenue;
+pub mod saga;
+pub mod schema;
+pub mod store;
+pub mod stuck;
+pub mod subscription;
+#[cfg(test)]
+pub mod test_support;
+pub mod timer;
+pub mod tracking;
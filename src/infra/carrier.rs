@@ -0,0 +1,233 @@
+use std::collections::HashMap;
+
+use crate::entities::{Action, DeliveryType, Order, OrderEvent, OrderItemId, Reason, ShipmentId};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CarrierError {
+    UnknownTrackingNumber(String),
+    NotBookable(Action),
+    NotCancellable(String),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TrackingStatus {
+    Booked,
+    InTransit,
+    Delivered,
+    Failed(Reason),
+    Cancelled,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Booking {
+    pub shipment_id: ShipmentId,
+    pub delivery_type: DeliveryType,
+    pub tracking_number: String,
+    pub items: Vec<OrderItemId>,
+}
+
+/// A carrier we can hand shipments to. Implementations talk to the carrier's API; the mocks below keep
+/// everything in memory.
+pub trait CarrierAdapter {
+    fn delivery_type(&self) -> DeliveryType;
+    fn book_shipment(&mut self, order: &Order, items: Vec<OrderItemId>) -> Result<Booking, CarrierError>;
+    fn get_label(&self, tracking_number: &str) -> Result<Vec<u8>, CarrierError>;
+    fn poll_tracking(&self, tracking_number: &str) -> Result<TrackingStatus, CarrierError>;
+    fn cancel(&mut self, tracking_number: &str) -> Result<(), CarrierError>;
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MockCarrier {
+    delivery_type: DeliveryType,
+    next_number: u32,
+    shipments: HashMap<String, (Booking, TrackingStatus)>,
+}
+
+impl MockCarrier {
+    pub fn new(delivery_type: DeliveryType) -> Self {
+        Self { delivery_type, next_number: 1, shipments: HashMap::new() }
+    }
+
+    pub fn gls() -> Self {
+        Self::new(DeliveryType::Gls)
+    }
+
+    pub fn ups() -> Self {
+        Self::new(DeliveryType::Ups)
+    }
+
+    pub fn bring() -> Self {
+        Self::new(DeliveryType::Bring)
+    }
+
+    /// Moves a booked shipment to a new tracking status, standing in for the carrier's own progress.
+    pub fn set_status(&mut self, tracking_number: &str, status: TrackingStatus) -> Result<(), CarrierError> {
+        let (_, current) = self
+            .shipments
+            .get_mut(tracking_number)
+            .ok_or_else(|| CarrierError::UnknownTrackingNumber(tracking_number.to_string()))?;
+        *current = status;
+        Ok(())
+    }
+
+    fn tracking_number(&self) -> String {
+        match self.delivery_type {
+            DeliveryType::Gls => format!("GLS{:011}", self.next_number),
+            DeliveryType::Ups => format!("1Z{:016}", self.next_number),
+            DeliveryType::Bring => format!("BR{:09}NO", self.next_number),
+        }
+    }
+}
+
+impl CarrierAdapter for MockCarrier {
+    fn delivery_type(&self) -> DeliveryType {
+        self.delivery_type
+    }
+
+    fn book_shipment(&mut self, order: &Order, items: Vec<OrderItemId>) -> Result<Booking, CarrierError> {
+        if !order.pending_actions.iter().any(|pending| pending.action == Action::PrepareOrder) {
+            return Err(CarrierError::NotBookable(order.action.clone()));
+        }
+        let tracking_number = self.tracking_number();
+        let booking = Booking {
+            shipment_id: format!("{}-{}", order.id, order.shipments.len() + 1),
+            delivery_type: self.delivery_type,
+            tracking_number: tracking_number.clone(),
+            items,
+        };
+        self.next_number += 1;
+        self.shipments.insert(tracking_number, (booking.clone(), TrackingStatus::Booked));
+        Ok(booking)
+    }
+
+    fn get_label(&self, tracking_number: &str) -> Result<Vec<u8>, CarrierError> {
+        let (booking, _) = self
+            .shipments
+            .get(tracking_number)
+            .ok_or_else(|| CarrierError::UnknownTrackingNumber(tracking_number.to_string()))?;
+        Ok(format!("{:?} {} {}", booking.delivery_type, booking.tracking_number, booking.shipment_id).into_bytes())
+    }
+
+    fn poll_tracking(&self, tracking_number: &str) -> Result<TrackingStatus, CarrierError> {
+        self.shipments
+            .get(tracking_number)
+            .map(|(_, status)| status.clone())
+            .ok_or_else(|| CarrierError::UnknownTrackingNumber(tracking_number.to_string()))
+    }
+
+    fn cancel(&mut self, tracking_number: &str) -> Result<(), CarrierError> {
+        let (_, status) = self
+            .shipments
+            .get_mut(tracking_number)
+            .ok_or_else(|| CarrierError::UnknownTrackingNumber(tracking_number.to_string()))?;
+        if *status != TrackingStatus::Booked {
+            return Err(CarrierError::NotCancellable(tracking_number.to_string()));
+        }
+        *status = TrackingStatus::Cancelled;
+        Ok(())
+    }
+}
+
+/// Fulfils `Action::PrepareOrder` by booking every item in a single shipment and returning the resulting event.
+pub fn prepare_order(order: &Order, carrier: &mut impl CarrierAdapter, time: u32) -> Result<OrderEvent, CarrierError> {
+    let booking = carrier.book_shipment(order, order.items.clone())?;
+    Ok(OrderEvent::ShipmentSent {
+        order_id: order.id.clone(),
+        shipment_id: booking.shipment_id,
+        delivery_type: booking.delivery_type,
+        tracking_number: booking.tracking_number,
+        items: booking.items,
+        time,
+    })
+}
+
+/// Translates a polled tracking status into the event it implies, if any.
+pub fn tracking_event(order_id: &str, shipment_id: &str, status: TrackingStatus, time: u32) -> Option<OrderEvent> {
+    match status {
+        TrackingStatus::Delivered => {
+            Some(OrderEvent::ShipmentDelivered { order_id: order_id.to_string(), shipment_id: shipment_id.to_string(), time })
+        }
+        TrackingStatus::Failed(reason) => {
+            Some(OrderEvent::ShipmentDeliveryFailed { order_id: order_id.to_string(), shipment_id: shipment_id.to_string(), reason, time })
+        }
+        TrackingStatus::Booked | TrackingStatus::InTransit | TrackingStatus::Cancelled => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        entities::{OrderEventDiscriminants, PaymentType, ReasonCode, State},
+        logic::{aggregate_order, TRANSITIONS},
+    };
+    use fsm::StateMachine;
+    use strum::IntoEnumIterator;
+
+    fn paid_events() -> Vec<OrderEvent> {
+        vec![
+            OrderEvent::ItemAdded { id: "1234".to_string(), order_id: "1234".to_string(), time: 1 },
+            OrderEvent::ItemAdded { id: "2345".to_string(), order_id: "1234".to_string(), time: 2 },
+            OrderEvent::OrderPayed { order_id: "1234".to_string(), payment_type: PaymentType::Visa, amount: 345, time: 3 },
+        ]
+    }
+
+    #[test]
+    fn prepare_order_end_to_end() {
+        for mut carrier in [MockCarrier::gls(), MockCarrier::ups(), MockCarrier::bring()] {
+            let mut events = paid_events();
+            let mut machine = StateMachine::new(State::iter().collect(), OrderEventDiscriminants::iter().collect(), TRANSITIONS.to_owned());
+            let order = aggregate_order(events.clone(), Order::new("1234".to_string()), &mut machine);
+            assert_eq!(order.action, Action::PrepareOrder);
+
+            let sent = prepare_order(&order, &mut carrier, 4).expect("booking failed");
+            let OrderEvent::ShipmentSent { shipment_id, tracking_number, .. } = sent.clone() else {
+                panic!("expected ShipmentSent")
+            };
+            assert!(!carrier.get_label(&tracking_number).expect("no label").is_empty());
+            events.push(sent);
+
+            carrier.set_status(&tracking_number, TrackingStatus::Delivered).expect("unknown shipment");
+            let status = carrier.poll_tracking(&tracking_number).expect("unknown shipment");
+            events.extend(tracking_event(&order.id, &shipment_id, status, 5));
+
+            let mut machine = StateMachine::new(State::iter().collect(), OrderEventDiscriminants::iter().collect(), TRANSITIONS.to_owned());
+            let order = aggregate_order(events, Order::new("1234".to_string()), &mut machine);
+            assert_eq!(order.status, State::Delivered);
+            assert_eq!(order.shipments[0].delivery_type, carrier.delivery_type());
+        }
+    }
+
+    #[test]
+    fn tracking_failure_contacts_customer() {
+        let mut events = paid_events();
+        let mut machine = StateMachine::new(State::iter().collect(), OrderEventDiscriminants::iter().collect(), TRANSITIONS.to_owned());
+        let order = aggregate_order(events.clone(), Order::new("1234".to_string()), &mut machine);
+        let mut carrier = MockCarrier::gls();
+        let sent = prepare_order(&order, &mut carrier, 4).expect("booking failed");
+        let OrderEvent::ShipmentSent { shipment_id, tracking_number, .. } = sent.clone() else {
+            panic!("expected ShipmentSent")
+        };
+        events.push(sent);
+
+        let reason = Reason { reason_code: ReasonCode::WrongAddress, reason_message: "Unknown street".to_string() };
+        carrier.set_status(&tracking_number, TrackingStatus::Failed(reason)).expect("unknown shipment");
+        let status = carrier.poll_tracking(&tracking_number).expect("unknown shipment");
+        events.extend(tracking_event(&order.id, &shipment_id, status, 5));
+
+        let mut machine = StateMachine::new(State::iter().collect(), OrderEventDiscriminants::iter().collect(), TRANSITIONS.to_owned());
+        let order = aggregate_order(events, Order::new("1234".to_string()), &mut machine);
+        assert_eq!((order.status, order.action), (State::DeliveryFailed, Action::ContactCustomer));
+    }
+
+    #[test]
+    fn cancel_only_booked_shipments() {
+        let mut machine = StateMachine::new(State::iter().collect(), OrderEventDiscriminants::iter().collect(), TRANSITIONS.to_owned());
+        let order = aggregate_order(paid_events(), Order::new("1234".to_string()), &mut machine);
+        let mut carrier = MockCarrier::gls();
+        let booking = carrier.book_shipment(&order, order.items.clone()).expect("booking failed");
+        carrier.set_status(&booking.tracking_number, TrackingStatus::InTransit).expect("unknown shipment");
+        assert_eq!(carrier.cancel(&booking.tracking_number), Err(CarrierError::NotCancellable(booking.tracking_number.clone())));
+        assert_eq!(carrier.cancel("unknown"), Err(CarrierError::UnknownTrackingNumber("unknown".to_string())));
+    }
+}
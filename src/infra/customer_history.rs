@@ -0,0 +1,128 @@
+use std::collections::HashMap;
+
+use crate::{
+    entities::{CustomerId, Order, OrderEvent, OrderId, State},
+    infra::{projection::Projection, subscription::EventEnvelope},
+};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CustomerOrder {
+    pub id: OrderId,
+    pub status: State,
+    pub amount: u32,
+    pub items: usize,
+    pub updated_at: u32,
+}
+
+/// A customer's orders, most recently updated first, with what they add up to.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct OrderHistory {
+    pub orders: Vec<CustomerOrder>,
+    pub total_amount: u32,
+    pub last_updated: u32,
+}
+
+/// Maps each customer to their orders. An order belongs to the customer `Order::customer` names, so it moves
+/// if a later event settles on a different customer.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CustomerHistory {
+    orders: HashMap<CustomerId, HashMap<OrderId, CustomerOrder>>,
+    owners: HashMap<OrderId, CustomerId>,
+    checkpoint: usize,
+}
+
+impl CustomerHistory {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn history(&self, customer: &str) -> OrderHistory {
+        let mut orders: Vec<CustomerOrder> = self.orders.get(customer).map(|orders| orders.values().cloned().collect()).unwrap_or_default();
+        orders.sort_by(|a, b| b.updated_at.cmp(&a.updated_at).then_with(|| a.id.cmp(&b.id)));
+        OrderHistory {
+            total_amount: orders.iter().map(|order| order.amount).sum(),
+            last_updated: orders.first().map(|order| order.updated_at).unwrap_or_default(),
+            orders,
+        }
+    }
+}
+
+impl Projection for CustomerHistory {
+    fn name(&self) -> &'static str {
+        "customer_history"
+    }
+
+    fn handle(&mut self, envelope: &EventEnvelope<OrderEvent>, order: &Order) -> Result<(), String> {
+        if envelope.position <= self.checkpoint {
+            return Ok(());
+        }
+        self.checkpoint = envelope.position;
+        let Some(customer) = &order.customer else {
+            return Ok(());
+        };
+        let previous = match self.owners.insert(envelope.stream.clone(), customer.clone()) {
+            Some(owner) => self.orders.get_mut(&owner).and_then(|orders| orders.remove(&envelope.stream)),
+            None => None,
+        };
+        let time = envelope.event.time();
+        self.orders.entry(customer.clone()).or_default().insert(
+            envelope.stream.clone(),
+            CustomerOrder {
+                id: envelope.stream.clone(),
+                status: order.status,
+                amount: order.amount,
+                items: order.items.len(),
+                updated_at: previous.map_or(time, |previous| previous.updated_at.max(time)),
+            },
+        );
+        Ok(())
+    }
+
+    fn checkpoint(&self) -> usize {
+        self.checkpoint
+    }
+
+    fn reset(&mut self) {
+        *self = Self::new();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::mpsc::channel;
+
+    use super::*;
+    use crate::{
+        entities::{DeliveryType, PaymentType},
+        infra::{
+            projection::Projector,
+            store::OrderStore,
+            test_support::{append_all, details_added, item_added, payed, place},
+        },
+    };
+
+    #[test]
+    fn lists_orders_per_customer() {
+        let mut store = OrderStore::new();
+        place(&mut store, "1001", DeliveryType::Gls, 10);
+        place(&mut store, "1002", DeliveryType::Gls, 20);
+        append_all(&mut store, "1003", [item_added("1003", 30), details_added("1003", DeliveryType::Gls, "54321", 31)]);
+        append_all(&mut store, "1001", [payed("1001", PaymentType::Visa, 345, 40)]);
+
+        let mut history = CustomerHistory::new();
+        Projector::new("customer_history", channel().1).run(&mut history, &store).expect("run failed");
+        let steen = history.history("765432");
+        assert_eq!(steen.orders.iter().map(|order| order.id.as_str()).collect::<Vec<_>>(), vec!["1001", "1002"]);
+        assert_eq!(steen.orders[0].status, State::Payed);
+        assert_eq!(steen.total_amount, 345);
+        assert_eq!(steen.last_updated, 40);
+        assert_eq!(history.history("54321").orders.len(), 1);
+        assert!(history.history("999").orders.is_empty());
+
+        let mut rebuilt = CustomerHistory::new();
+        Projector::new("customer_history", channel().1)
+            .rebuild(&mut rebuilt, &store)
+            .expect("rebuild failed");
+        assert_eq!(rebuilt, history);
+    }
+}
@@ -0,0 +1,240 @@
+use std::collections::HashMap;
+
+use crate::{
+    entities::{DeliveryType, Order, OrderEvent, OrderId, ReasonCode, ShipmentId, State},
+    infra::{projection::Projection, subscription::EventEnvelope},
+};
+
+/// How long each fulfilment step may take, in seconds, before it counts as an SLA breach.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct SlaTargets {
+    pub pay_to_send: u32,
+    pub send_to_deliver: u32,
+}
+
+/// A latency distribution in seconds, by nearest rank. All zero when there are no samples.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub struct Latencies {
+    pub count: usize,
+    pub p50: u32,
+    pub p95: u32,
+    pub p99: u32,
+}
+
+impl Latencies {
+    fn of(samples: &[u32]) -> Self {
+        let mut sorted = samples.to_vec();
+        sorted.sort_unstable();
+        let rank = |percentile: usize| match sorted.len() {
+            0 => 0,
+            len => sorted[(len * percentile).div_ceil(100).max(1) - 1],
+        };
+        Self { count: sorted.len(), p50: rank(50), p95: rank(95), p99: rank(99) }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct CarrierReport {
+    pub delivery_type: DeliveryType,
+    pub pay_to_send: Latencies,
+    pub send_to_deliver: Latencies,
+    pub pay_to_send_breaches: usize,
+    pub send_to_deliver_breaches: usize,
+    pub shipments: u32,
+    /// Share of shipments that failed, per reason.
+    pub failure_rates: HashMap<ReasonCode, f64>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+struct CarrierSamples {
+    pay_to_send: Vec<u32>,
+    send_to_deliver: Vec<u32>,
+    shipments: u32,
+    failures: HashMap<ReasonCode, u32>,
+}
+
+/// Fulfilment latencies per carrier: from payment to `OrderSent`, and from `OrderSent` to `OrderDelivered`.
+/// An order counts as paid at `OrderPayed` or `PaymentAuthorized`, whichever comes first, as that is when the
+/// customer is done paying. A re-sent order is timed from its latest send. An order sent in shipments is timed
+/// per shipment, from `ShipmentSent` to `ShipmentDelivered`, on the shipment's own carrier; a failed shipment
+/// counts against that carrier only.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FulfilmentMetrics {
+    targets: SlaTargets,
+    paid_at: HashMap<OrderId, u32>,
+    /// In-flight sends by order and shipment (`None` for a whole order), with their carrier and send time.
+    sent_at: HashMap<(OrderId, Option<ShipmentId>), (DeliveryType, u32)>,
+    carriers: HashMap<DeliveryType, CarrierSamples>,
+    checkpoint: usize,
+}
+
+impl FulfilmentMetrics {
+    pub fn new(targets: SlaTargets) -> Self {
+        Self { targets, paid_at: HashMap::new(), sent_at: HashMap::new(), carriers: HashMap::new(), checkpoint: 0 }
+    }
+
+    pub fn report(&self, delivery_type: DeliveryType) -> Option<CarrierReport> {
+        let samples = self.carriers.get(&delivery_type)?;
+        Some(CarrierReport {
+            delivery_type,
+            pay_to_send: Latencies::of(&samples.pay_to_send),
+            send_to_deliver: Latencies::of(&samples.send_to_deliver),
+            pay_to_send_breaches: samples.pay_to_send.iter().filter(|latency| **latency > self.targets.pay_to_send).count(),
+            send_to_deliver_breaches: samples.send_to_deliver.iter().filter(|latency| **latency > self.targets.send_to_deliver).count(),
+            shipments: samples.shipments,
+            failure_rates: samples
+                .failures
+                .iter()
+                .map(|(reason_code, failures)| (*reason_code, f64::from(*failures) / f64::from(samples.shipments.max(1))))
+                .collect(),
+        })
+    }
+
+    /// Times a send from payment. Every shipment of an order is timed from the same payment.
+    fn sent(&mut self, id: &OrderId, shipment_id: Option<ShipmentId>, delivery_type: DeliveryType, time: u32) {
+        let samples = self.carriers.entry(delivery_type).or_default();
+        samples.shipments += 1;
+        if let Some(paid_at) = self.paid_at.get(id) {
+            samples.pay_to_send.push(time.saturating_sub(*paid_at));
+        }
+        self.sent_at.insert((id.clone(), shipment_id), (delivery_type, time));
+    }
+
+    fn delivered(&mut self, id: &OrderId, shipment_id: Option<ShipmentId>, time: u32) {
+        if let Some((delivery_type, sent_at)) = self.sent_at.remove(&(id.clone(), shipment_id)) {
+            self.carriers.entry(delivery_type).or_default().send_to_deliver.push(time.saturating_sub(sent_at));
+        }
+    }
+
+    fn failed(&mut self, id: &OrderId, shipment_id: Option<ShipmentId>, reason_code: ReasonCode) {
+        if let Some((delivery_type, _)) = self.sent_at.remove(&(id.clone(), shipment_id)) {
+            *self.carriers.entry(delivery_type).or_default().failures.entry(reason_code).or_default() += 1;
+        }
+    }
+}
+
+impl Projection for FulfilmentMetrics {
+    fn name(&self) -> &'static str {
+        "fulfilment_metrics"
+    }
+
+    fn handle(&mut self, envelope: &EventEnvelope<OrderEvent>, order: &Order) -> Result<(), String> {
+        if envelope.position <= self.checkpoint {
+            return Ok(());
+        }
+        self.checkpoint = envelope.position;
+        let id = &envelope.stream;
+        match &envelope.event {
+            OrderEvent::OrderPayed { time, .. } | OrderEvent::PaymentAuthorized { time, .. } => {
+                self.paid_at.entry(id.clone()).or_insert(*time);
+            }
+            OrderEvent::OrderSent { time, .. } => {
+                if let Some(delivery_type) = order.delivery_type {
+                    self.sent(id, None, delivery_type, *time);
+                }
+            }
+            OrderEvent::ShipmentSent { shipment_id, delivery_type, time, .. } => {
+                self.sent(id, Some(shipment_id.clone()), *delivery_type, *time);
+            }
+            OrderEvent::OrderDelivered { time, .. } => self.delivered(id, None, *time),
+            OrderEvent::ShipmentDelivered { shipment_id, time, .. } => self.delivered(id, Some(shipment_id.clone()), *time),
+            OrderEvent::OrderDeliveryFailed { reason, .. } => self.failed(id, None, reason.reason_code),
+            OrderEvent::ShipmentDeliveryFailed { shipment_id, reason, .. } => {
+                self.failed(id, Some(shipment_id.clone()), reason.reason_code);
+            }
+            _ => {}
+        }
+        if matches!(order.status, State::Sent | State::Delivered) {
+            self.paid_at.remove(id);
+        }
+        Ok(())
+    }
+
+    fn checkpoint(&self) -> usize {
+        self.checkpoint
+    }
+
+    fn reset(&mut self) {
+        *self = Self::new(self.targets);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::mpsc::channel;
+
+    use super::*;
+    use crate::{
+        entities::{PaymentType, Reason},
+        infra::{
+            projection::Projector,
+            store::OrderStore,
+            test_support::{append_all, delivered, delivery_failed, details_added, item_added, pay, payed, sent, CUSTOMER},
+        },
+    };
+
+    fn ship(store: &mut OrderStore, order_id: &str, delivery_type: DeliveryType, paid: u32, sent_at: u32) {
+        pay(store, order_id, delivery_type, paid);
+        append_all(store, order_id, [sent(order_id, sent_at)]);
+    }
+
+    fn shipment_sent(order_id: &str, shipment_id: &str, time: u32) -> OrderEvent {
+        OrderEvent::ShipmentSent {
+            order_id: order_id.to_string(),
+            shipment_id: shipment_id.to_string(),
+            delivery_type: DeliveryType::Bring,
+            tracking_number: format!("BR-{shipment_id}"),
+            items: vec![shipment_id.to_string()],
+            time,
+        }
+    }
+
+    #[test]
+    fn latencies_breaches_and_failure_rates() {
+        let mut store = OrderStore::new();
+        for (number, pay_to_send) in (1..=10).zip([10, 20, 30, 40, 50, 60, 70, 80, 90, 500]) {
+            let order_id = format!("10{number:02}");
+            ship(&mut store, &order_id, DeliveryType::Gls, 1000, 1000 + pay_to_send);
+            let outcome = match number {
+                1..=7 => delivered(&order_id, 1000 + pay_to_send + 100),
+                _ => delivery_failed(&order_id, ReasonCode::PackageLost, 2000),
+            };
+            append_all(&mut store, &order_id, [outcome]);
+        }
+        ship(&mut store, "2001", DeliveryType::Ups, 1000, 1010);
+        append_all(
+            &mut store,
+            "3001",
+            [
+                item_added("3001", 997),
+                OrderEvent::ItemAdded { id: "3001-2".to_string(), order_id: "3001".to_string(), time: 998 },
+                details_added("3001", DeliveryType::Gls, CUSTOMER, 999),
+                payed("3001", PaymentType::Visa, 100, 1000),
+                shipment_sent("3001", "3001-1", 1020),
+                shipment_sent("3001", "3001-2", 1040),
+                OrderEvent::ShipmentDelivered { order_id: "3001".to_string(), shipment_id: "3001-1".to_string(), time: 1120 },
+                OrderEvent::ShipmentDeliveryFailed {
+                    order_id: "3001".to_string(),
+                    shipment_id: "3001-2".to_string(),
+                    reason: Reason { reason_code: ReasonCode::PackageLost, reason_message: String::new() },
+                    time: 2000,
+                },
+            ],
+        );
+
+        let mut metrics = FulfilmentMetrics::new(SlaTargets { pay_to_send: 60, send_to_deliver: 86_400 });
+        Projector::new("fulfilment_metrics", channel().1).run(&mut metrics, &store).expect("run failed");
+        let gls = metrics.report(DeliveryType::Gls).expect("no GLS report");
+        assert_eq!(gls.pay_to_send, Latencies { count: 10, p50: 50, p95: 500, p99: 500 });
+        assert_eq!(gls.send_to_deliver, Latencies { count: 7, p50: 100, p95: 100, p99: 100 });
+        assert_eq!(gls.pay_to_send_breaches, 4);
+        assert_eq!(gls.send_to_deliver_breaches, 0);
+        assert_eq!(gls.failure_rates.get(&ReasonCode::PackageLost), Some(&0.3));
+        assert_eq!(metrics.report(DeliveryType::Ups).map(|ups| ups.shipments), Some(1));
+        let bring = metrics.report(DeliveryType::Bring).expect("no Bring report");
+        assert_eq!((bring.shipments, bring.pay_to_send), (2, Latencies { count: 2, p50: 20, p95: 40, p99: 40 }));
+        assert_eq!(bring.send_to_deliver, Latencies { count: 1, p50: 100, p95: 100, p99: 100 });
+        assert_eq!(bring.failure_rates.get(&ReasonCode::PackageLost), Some(&0.5));
+        assert_eq!(metrics.report(DeliveryType::Gls).map(|gls| gls.shipments), Some(10));
+    }
+}
@@ -0,0 +1,38 @@
+use std::{fs, io::ErrorKind, path::PathBuf};
+
+use serde::{de::DeserializeOwned, Serialize};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FileError {
+    Io(String),
+    Malformed(String),
+}
+
+/// A list of records kept as one JSON file, for state that has to survive a restart. A missing file means an
+/// empty list.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct JsonFile {
+    path: PathBuf,
+}
+
+impl JsonFile {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self { path: path.into() }
+    }
+
+    pub fn load<T: DeserializeOwned>(&self) -> Result<Vec<T>, FileError> {
+        match fs::read(&self.path) {
+            Ok(bytes) => serde_json::from_slice(&bytes).map_err(|err| FileError::Malformed(err.to_string())),
+            Err(err) if err.kind() == ErrorKind::NotFound => Ok(Vec::new()),
+            Err(err) => Err(FileError::Io(err.to_string())),
+        }
+    }
+
+    pub fn save<T: Serialize>(&self, records: &[T]) -> Result<(), FileError> {
+        let bytes = serde_json::to_vec(records).map_err(|err| FileError::Malformed(err.to_string()))?;
+        // Write then rename, so a crash never leaves half a file behind.
+        let partial = self.path.with_extension("partial");
+        fs::write(&partial, bytes).map_err(|err| FileError::Io(err.to_string()))?;
+        fs::rename(&partial, &self.path).map_err(|err| FileError::Io(err.to_string()))
+    }
+}
@@ -0,0 +1,401 @@
+use std::{collections::HashMap, path::PathBuf};
+
+use chacha20poly1305::{
+    aead::{Aead, NewAead},
+    ChaCha20Poly1305, Key, Nonce,
+};
+use rand::RngCore;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+
+use crate::{
+    entities::{CustomerEvent, CustomerId, DeliveryResolution, OrderEvent, Pii, Sealed},
+    infra::json_file::{FileError, JsonFile},
+};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum KeyStoreError {
+    Serialization(String),
+    Encryption(CustomerId),
+    Storage(FileError),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+struct CustomerKey {
+    customer: CustomerId,
+    key: [u8; 32],
+}
+
+/// Per-customer encryption keys for personal data in events. Deleting a customer's key (`shred`) is how we
+/// erase them: the events stay in the stream, but their personal data can never be decrypted again. Losing the
+/// keys erases everyone, so a store opened on a file writes every new key and every shred to it before returning.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct LocalKeyStore {
+    keys: HashMap<CustomerId, [u8; 32]>,
+    file: Option<JsonFile>,
+}
+
+impl LocalKeyStore {
+    /// A store that only keeps its keys in memory, for tests and tools that never outlive their events.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// A store kept as a JSON file. A missing file means no customer has a key yet.
+    pub fn open(path: impl Into<PathBuf>) -> Result<Self, KeyStoreError> {
+        let file = JsonFile::new(path);
+        let keys = file.load().map_err(KeyStoreError::Storage)?;
+        Ok(Self { keys: keys.into_iter().map(|entry: CustomerKey| (entry.customer, entry.key)).collect(), file: Some(file) })
+    }
+
+    /// Deletes a customer's key. Returns whether there was one.
+    pub fn shred(&mut self, customer: &str) -> Result<bool, KeyStoreError> {
+        let shredded = self.keys.remove(customer).is_some();
+        if shredded {
+            self.save()?;
+        }
+        Ok(shredded)
+    }
+
+    pub fn seal<T: Serialize>(&mut self, customer: &str, value: &T) -> Result<Sealed, KeyStoreError> {
+        let plaintext = serde_json::to_vec(value).map_err(|err| KeyStoreError::Serialization(err.to_string()))?;
+        let key = if let Some(key) = self.keys.get(customer) {
+            *key
+        } else {
+            let mut key = [0; 32];
+            rand::thread_rng().fill_bytes(&mut key);
+            self.keys.insert(customer.to_string(), key);
+            if let Err(err) = self.save() {
+                self.keys.remove(customer);
+                return Err(err);
+            }
+            key
+        };
+        let mut nonce = [0; 12];
+        rand::thread_rng().fill_bytes(&mut nonce);
+        let ciphertext = ChaCha20Poly1305::new(&Key::from(key))
+            .encrypt(&Nonce::from(nonce), plaintext.as_ref())
+            .map_err(|_| KeyStoreError::Encryption(customer.to_string()))?;
+        Ok(Sealed { customer: customer.to_string(), nonce: nonce.to_vec(), ciphertext })
+    }
+
+    /// Decrypts a sealed value, or `None` if its customer's key is gone or the ciphertext does not match it.
+    pub fn unseal<T: DeserializeOwned>(&self, sealed: &Sealed) -> Option<T> {
+        let key = self.keys.get(&sealed.customer)?;
+        let nonce: [u8; 12] = sealed.nonce.as_slice().try_into().ok()?;
+        let plaintext = ChaCha20Poly1305::new(&Key::from(*key))
+            .decrypt(&Nonce::from(nonce), sealed.ciphertext.as_ref())
+            .ok()?;
+        serde_json::from_slice(&plaintext).ok()
+    }
+
+    fn save(&self) -> Result<(), KeyStoreError> {
+        let Some(file) = &self.file else {
+            return Ok(());
+        };
+        let mut keys: Vec<_> = self
+            .keys
+            .iter()
+            .map(|(customer, key)| CustomerKey { customer: customer.clone(), key: *key })
+            .collect();
+        keys.sort_by(|left, right| left.customer.cmp(&right.customer));
+        file.save(&keys).map_err(KeyStoreError::Storage)
+    }
+
+    fn seal_field<T: Serialize>(&mut self, customer: &str, field: Pii<T>) -> Result<Pii<T>, KeyStoreError> {
+        match field {
+            Pii::Plain(value) => Ok(Pii::Encrypted(self.seal(customer, &value)?)),
+            field => Ok(field),
+        }
+    }
+
+    fn unseal_field<T: DeserializeOwned>(&self, field: Pii<T>) -> Pii<T> {
+        match field {
+            Pii::Encrypted(sealed) => self.unseal(&sealed).map_or(Pii::Redacted, Pii::Plain),
+            field => field,
+        }
+    }
+}
+
+/// An event whose personal data is kept in `Pii` fields, so it can be sealed before it is stored.
+pub trait Sealable: Sized {
+    /// Encrypts every `Pii` field with the key of `owner`, unless the event names a customer of its own.
+    fn seal_with(self, owner: &str, keys: &mut LocalKeyStore) -> Result<Self, KeyStoreError>;
+    fn unseal_with(self, keys: &LocalKeyStore) -> Self;
+}
+
+impl Sealable for OrderEvent {
+    fn seal_with(self, owner: &str, keys: &mut LocalKeyStore) -> Result<Self, KeyStoreError> {
+        Ok(match self {
+            Self::CustomerAdded { customer, first_name, last_name, address, time } => Self::CustomerAdded {
+                first_name: keys.seal_field(&customer, first_name)?,
+                last_name: keys.seal_field(&customer, last_name)?,
+                address: keys.seal_field(&customer, address)?,
+                customer,
+                time,
+            },
+            Self::OrderDetailsAdded { order_id, delivery_type, delivery_address, customer, time } => Self::OrderDetailsAdded {
+                delivery_address: delivery_address.map(|address| keys.seal_field(&customer, address)).transpose()?,
+                order_id,
+                delivery_type,
+                customer,
+                time,
+            },
+            Self::AddressesResolved { order_id, shipping_address, billing_address, time } => Self::AddressesResolved {
+                order_id,
+                shipping_address: keys.seal_field(owner, shipping_address)?,
+                billing_address: keys.seal_field(owner, billing_address)?,
+                time,
+            },
+            Self::CustomerResponded { order_id, resolution: DeliveryResolution::Reship(address), time } => {
+                Self::CustomerResponded { order_id, resolution: DeliveryResolution::Reship(keys.seal_field(owner, address)?), time }
+            }
+            Self::OrderReshipped { order_id, address, time } => {
+                Self::OrderReshipped { order_id, address: keys.seal_field(owner, address)?, time }
+            }
+            event => event,
+        })
+    }
+
+    fn unseal_with(self, keys: &LocalKeyStore) -> Self {
+        match self {
+            Self::CustomerAdded { customer, first_name, last_name, address, time } => Self::CustomerAdded {
+                first_name: keys.unseal_field(first_name),
+                last_name: keys.unseal_field(last_name),
+                address: keys.unseal_field(address),
+                customer,
+                time,
+            },
+            Self::OrderDetailsAdded { order_id, delivery_type, delivery_address, customer, time } => Self::OrderDetailsAdded {
+                delivery_address: delivery_address.map(|address| keys.unseal_field(address)),
+                order_id,
+                delivery_type,
+                customer,
+                time,
+            },
+            Self::AddressesResolved { order_id, shipping_address, billing_address, time } => Self::AddressesResolved {
+                order_id,
+                shipping_address: keys.unseal_field(shipping_address),
+                billing_address: keys.unseal_field(billing_address),
+                time,
+            },
+            Self::CustomerResponded { order_id, resolution: DeliveryResolution::Reship(address), time } => {
+                Self::CustomerResponded { order_id, resolution: DeliveryResolution::Reship(keys.unseal_field(address)), time }
+            }
+            Self::OrderReshipped { order_id, address, time } => {
+                Self::OrderReshipped { order_id, address: keys.unseal_field(address), time }
+            }
+            event => event,
+        }
+    }
+}
+
+/// Customer events are always sealed with the key of the customer they belong to.
+impl Sealable for CustomerEvent {
+    fn seal_with(self, _owner: &str, keys: &mut LocalKeyStore) -> Result<Self, KeyStoreError> {
+        Ok(match self {
+            Self::CustomerRegistered { customer, first_name, last_name, email, address, time } => Self::CustomerRegistered {
+                first_name: keys.seal_field(&customer, first_name)?,
+                last_name: keys.seal_field(&customer, last_name)?,
+                email: keys.seal_field(&customer, email)?,
+                address: keys.seal_field(&customer, address)?,
+                customer,
+                time,
+            },
+            Self::CustomerAddressChanged { customer, name, address, time } => {
+                Self::CustomerAddressChanged { address: keys.seal_field(&customer, address)?, customer, name, time }
+            }
+            Self::CustomerEmailChanged { customer, email, time } => {
+                Self::CustomerEmailChanged { email: keys.seal_field(&customer, email)?, customer, time }
+            }
+            event => event,
+        })
+    }
+
+    fn unseal_with(self, keys: &LocalKeyStore) -> Self {
+        match self {
+            Self::CustomerRegistered { customer, first_name, last_name, email, address, time } => Self::CustomerRegistered {
+                first_name: keys.unseal_field(first_name),
+                last_name: keys.unseal_field(last_name),
+                email: keys.unseal_field(email),
+                address: keys.unseal_field(address),
+                customer,
+                time,
+            },
+            Self::CustomerAddressChanged { customer, name, address, time } => {
+                Self::CustomerAddressChanged { address: keys.unseal_field(address), customer, name, time }
+            }
+            Self::CustomerEmailChanged { customer, email, time } => {
+                Self::CustomerEmailChanged { email: keys.unseal_field(email), customer, time }
+            }
+            event => event,
+        }
+    }
+}
+
+/// Encrypts the personal data of an event before it is stored. `owner` is the customer the stream belongs to;
+/// it holds the key for events that don't name a customer themselves. Events without personal data pass through.
+pub fn seal_event<E: Sealable>(event: E, owner: &str, keys: &mut LocalKeyStore) -> Result<E, KeyStoreError> {
+    event.seal_with(owner, keys)
+}
+
+/// Decrypts the personal data of a stored event. Data of shredded customers comes back `Redacted`.
+pub fn unseal_event<E: Sealable>(event: E, keys: &LocalKeyStore) -> E {
+    event.unseal_with(keys)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+    use crate::{
+        entities::{Address, CountryCode, DeliveryType, Order, OrderEventDiscriminants, Redacted, State},
+        infra::store::{CustomerStore, OrderStore},
+        logic::{aggregate_order, TRANSITIONS},
+    };
+    use fsm::StateMachine;
+    use strum::IntoEnumIterator;
+
+    #[test]
+    fn shredded_customer_replays_redacted() {
+        let address = Address { street: "Taagevej".to_string(), house_number: 43, zip: 4600, country: CountryCode::Dk };
+        let mut keys = LocalKeyStore::new();
+        let mut store = OrderStore::new();
+        let customer_added = OrderEvent::CustomerAdded {
+            customer: "765432".to_string(),
+            first_name: Pii::Plain("Steen".to_string()),
+            last_name: Pii::Plain("Larsen".to_string()),
+            address: Pii::Plain(address),
+            time: 0,
+        };
+        store
+            .append("1234", seal_event(customer_added.clone(), "765432", &mut keys).expect("sealing failed"))
+            .expect("append failed");
+        store
+            .append("1234", OrderEvent::ItemAdded { id: "1234".to_string(), order_id: "1234".to_string(), time: 1 })
+            .expect("append failed");
+        let OrderEvent::CustomerAdded { first_name: Pii::Encrypted(_), .. } = &store.load("1234").expect("loading failed")[0] else {
+            panic!("stored in plain text")
+        };
+
+        let load = |keys: &LocalKeyStore| {
+            store
+                .load("1234")
+                .expect("loading failed")
+                .into_iter()
+                .map(|event| unseal_event(event, keys))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(load(&keys)[0], customer_added);
+
+        assert_eq!(keys.shred("765432"), Ok(true));
+        let events = load(&keys);
+        let OrderEvent::CustomerAdded { first_name: Pii::Redacted, address: Pii::Redacted, .. } = &events[0] else {
+            panic!("not redacted")
+        };
+        let mut machine = StateMachine::new(State::iter().collect(), OrderEventDiscriminants::iter().collect(), TRANSITIONS.to_owned());
+        let order = aggregate_order(events, Order::new("1234".to_string()), &mut machine);
+        assert_eq!(order.customer, Some("765432".to_string()));
+        assert_eq!(order.address, Some(Address::redacted()));
+    }
+
+    #[test]
+    fn keys_and_shreds_survive_reopening() {
+        let path = std::env::temp_dir().join(format!("customer_keys_{}.json", std::process::id()));
+        let _ = fs::remove_file(&path);
+        let mut keys = LocalKeyStore::open(&path).expect("loading keys failed");
+        let steen = keys.seal("765432", &"Steen".to_string()).expect("sealing failed");
+        let anna = keys.seal("123456", &"Anna".to_string()).expect("sealing failed");
+        drop(keys);
+
+        let mut keys = LocalKeyStore::open(&path).expect("loading keys failed");
+        assert_eq!(keys.unseal(&steen), Some("Steen".to_string()));
+        assert_eq!(keys.shred("765432"), Ok(true));
+
+        let keys = LocalKeyStore::open(&path).expect("loading keys failed");
+        assert_eq!(keys.unseal::<String>(&steen), None);
+        assert_eq!(keys.unseal(&anna), Some("Anna".to_string()));
+        let _ = fs::remove_file(&path);
+    }
+
+    #[test]
+    fn shredding_leaves_no_plaintext_in_either_store() {
+        let address = || Pii::Plain(Address { street: "Taagevej".to_string(), house_number: 43, zip: 4600, country: CountryCode::Dk });
+        let customer = || "765432".to_string();
+        let order = || "1234".to_string();
+        let mut keys = LocalKeyStore::new();
+        let mut orders = OrderStore::new();
+        let mut customers = CustomerStore::new();
+        for event in [
+            OrderEvent::CustomerAdded {
+                customer: customer(),
+                first_name: Pii::Plain("Steen".to_string()),
+                last_name: Pii::Plain("Larsen".to_string()),
+                address: address(),
+                time: 0,
+            },
+            OrderEvent::OrderDetailsAdded {
+                order_id: order(),
+                delivery_type: DeliveryType::Gls,
+                delivery_address: Some(address()),
+                customer: customer(),
+                time: 1,
+            },
+            OrderEvent::AddressesResolved { order_id: order(), shipping_address: address(), billing_address: address(), time: 2 },
+            OrderEvent::CustomerResponded { order_id: order(), resolution: DeliveryResolution::Reship(address()), time: 3 },
+            OrderEvent::OrderReshipped { order_id: order(), address: address(), time: 4 },
+        ] {
+            orders
+                .append("1234", seal_event(event, "765432", &mut keys).expect("sealing failed"))
+                .expect("append failed");
+        }
+        for event in [
+            CustomerEvent::CustomerRegistered {
+                customer: customer(),
+                first_name: Pii::Plain("Steen".to_string()),
+                last_name: Pii::Plain("Larsen".to_string()),
+                email: Pii::Plain("steen@example.dk".to_string()),
+                address: address(),
+                time: 0,
+            },
+            CustomerEvent::CustomerAddressChanged { customer: customer(), name: "home".to_string(), address: address(), time: 1 },
+            CustomerEvent::CustomerEmailChanged { customer: customer(), email: Pii::Plain("steen@example.dk".to_string()), time: 2 },
+        ] {
+            customers
+                .append("765432", seal_event(event, "765432", &mut keys).expect("sealing failed"))
+                .expect("append failed");
+        }
+        let plaintext = |dump: String| ["Steen", "Larsen", "Taagevej", "steen@"].iter().any(|text| dump.contains(text));
+        assert!(!plaintext(format!("{orders:?}{customers:?}")));
+        assert!(plaintext(format!(
+            "{:?}",
+            customers
+                .load("765432")
+                .expect("loading failed")
+                .into_iter()
+                .map(|event| unseal_event(event, &keys))
+                .collect::<Vec<_>>()
+        )));
+
+        assert_eq!(keys.shred("765432"), Ok(true));
+        let orders = orders
+            .load("1234")
+            .expect("loading failed")
+            .into_iter()
+            .map(|event| unseal_event(event, &keys))
+            .collect::<Vec<_>>();
+        let customers = customers
+            .load("765432")
+            .expect("loading failed")
+            .into_iter()
+            .map(|event| unseal_event(event, &keys))
+            .collect::<Vec<_>>();
+        assert!(!plaintext(format!("{orders:?}{customers:?}")));
+        let OrderEvent::OrderReshipped { address: Pii::Redacted, .. } = &orders[4] else {
+            panic!("not redacted")
+        };
+        let CustomerEvent::CustomerEmailChanged { email: Pii::Redacted, .. } = &customers[2] else {
+            panic!("not redacted")
+        };
+    }
+}
@@ -0,0 +1,300 @@
+use std::{
+    collections::{BTreeSet, HashMap},
+    ops::Range,
+};
+
+use crate::{
+    entities::{Action, CustomerId, DeliveryType, Order, OrderEvent, OrderId, State},
+    infra::{projection::Projection, subscription::EventEnvelope},
+};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum QueryError {
+    InvalidCursor(String),
+}
+
+/// One row of the order list: what ops staff need to find an order, not the whole aggregate.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OrderSummary {
+    pub id: OrderId,
+    pub status: State,
+    pub action: Action,
+    pub delivery_type: Option<DeliveryType>,
+    pub customer: Option<CustomerId>,
+    pub amount: u32,
+    pub items: usize,
+    pub placed_at: u32,
+    pub updated_at: u32,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+pub enum OrderSort {
+    #[default]
+    PlacedAt,
+    UpdatedAt,
+    Amount,
+}
+
+/// Filters are combined with AND; `None` matches everything. `placed` is a range of `placed_at` times. Pass the
+/// `next` cursor of a page as `after` to get the page following it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OrderQuery {
+    pub status: Option<State>,
+    pub action: Option<Action>,
+    pub delivery_type: Option<DeliveryType>,
+    pub customer: Option<CustomerId>,
+    pub placed: Option<Range<u32>>,
+    pub sort: OrderSort,
+    pub descending: bool,
+    pub limit: usize,
+    pub after: Option<String>,
+}
+
+impl Default for OrderQuery {
+    fn default() -> Self {
+        Self {
+            status: None,
+            action: None,
+            delivery_type: None,
+            customer: None,
+            placed: None,
+            sort: OrderSort::default(),
+            descending: false,
+            limit: 50,
+            after: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OrderPage {
+    pub orders: Vec<OrderSummary>,
+    pub next: Option<String>,
+}
+
+/// The order list read model: a summary per order, indexed by everything `OrderQuery` filters on.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct OrderList {
+    orders: HashMap<OrderId, OrderSummary>,
+    by_status: HashMap<State, BTreeSet<OrderId>>,
+    by_action: HashMap<Action, BTreeSet<OrderId>>,
+    by_delivery_type: HashMap<DeliveryType, BTreeSet<OrderId>>,
+    by_customer: HashMap<CustomerId, BTreeSet<OrderId>>,
+    by_placed_at: BTreeSet<(u32, OrderId)>,
+    checkpoint: usize,
+}
+
+impl OrderList {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn get(&self, id: &str) -> Option<&OrderSummary> {
+        self.orders.get(id)
+    }
+
+    pub fn query(&self, query: &OrderQuery) -> Result<OrderPage, QueryError> {
+        let after = query.after.as_deref().map(parse_cursor).transpose()?;
+        let mut rows: Vec<(u32, &OrderSummary)> = self
+            .candidates(query)
+            .into_iter()
+            .filter_map(|id| self.orders.get(&id))
+            .filter(|summary| matches(summary, query))
+            .map(|summary| (sort_key(summary, query.sort), summary))
+            .collect();
+        rows.sort_by(|(a, left), (b, right)| (a, &left.id).cmp(&(b, &right.id)));
+        if query.descending {
+            rows.reverse();
+        }
+        let rows: Vec<_> = rows
+            .into_iter()
+            .filter(|(key, summary)| match &after {
+                Some((after_key, after_id)) if query.descending => (key, &summary.id) < (after_key, after_id),
+                Some((after_key, after_id)) => (key, &summary.id) > (after_key, after_id),
+                None => true,
+            })
+            .collect();
+        let more = rows.len() > query.limit;
+        let orders: Vec<OrderSummary> = rows.into_iter().take(query.limit).map(|(_, summary)| summary.clone()).collect();
+        let next = orders
+            .last()
+            .filter(|_| more)
+            .map(|summary| format!("{}:{}", sort_key(summary, query.sort), summary.id));
+        Ok(OrderPage { orders, next })
+    }
+
+    /// Order ids from the smallest index the query filters on, or every order if it filters on none.
+    fn candidates(&self, query: &OrderQuery) -> BTreeSet<OrderId> {
+        let empty = BTreeSet::new();
+        let placed = query.placed.as_ref().map(|placed| {
+            self.by_placed_at
+                .range((placed.start, String::new())..(placed.end, String::new()))
+                .map(|(_, id)| id.clone())
+                .collect::<BTreeSet<_>>()
+        });
+        [
+            query.status.map(|status| self.by_status.get(&status).unwrap_or(&empty)),
+            query.action.as_ref().map(|action| self.by_action.get(action).unwrap_or(&empty)),
+            query.delivery_type.map(|delivery_type| self.by_delivery_type.get(&delivery_type).unwrap_or(&empty)),
+            query.customer.as_ref().map(|customer| self.by_customer.get(customer).unwrap_or(&empty)),
+            placed.as_ref(),
+        ]
+        .into_iter()
+        .flatten()
+        .min_by_key(|ids| ids.len())
+        .cloned()
+        .unwrap_or_else(|| self.orders.keys().cloned().collect())
+    }
+
+    fn index(&mut self, summary: &OrderSummary) {
+        let id = summary.id.clone();
+        self.by_status.entry(summary.status).or_default().insert(id.clone());
+        self.by_action.entry(summary.action.clone()).or_default().insert(id.clone());
+        if let Some(delivery_type) = summary.delivery_type {
+            self.by_delivery_type.entry(delivery_type).or_default().insert(id.clone());
+        }
+        if let Some(customer) = &summary.customer {
+            self.by_customer.entry(customer.clone()).or_default().insert(id.clone());
+        }
+        self.by_placed_at.insert((summary.placed_at, id));
+    }
+
+    fn unindex(&mut self, summary: &OrderSummary) {
+        let id = &summary.id;
+        self.by_status.get_mut(&summary.status).map(|ids| ids.remove(id));
+        self.by_action.get_mut(&summary.action).map(|ids| ids.remove(id));
+        if let Some(delivery_type) = summary.delivery_type {
+            self.by_delivery_type.get_mut(&delivery_type).map(|ids| ids.remove(id));
+        }
+        if let Some(customer) = &summary.customer {
+            self.by_customer.get_mut(customer).map(|ids| ids.remove(id));
+        }
+        self.by_placed_at.remove(&(summary.placed_at, id.clone()));
+    }
+}
+
+impl Projection for OrderList {
+    fn name(&self) -> &'static str {
+        "order_list"
+    }
+
+    fn handle(&mut self, envelope: &EventEnvelope<OrderEvent>, order: &Order) -> Result<(), String> {
+        if envelope.position <= self.checkpoint {
+            return Ok(());
+        }
+        let time = envelope.event.time();
+        let previous = self.orders.remove(&envelope.stream);
+        if let Some(previous) = &previous {
+            self.unindex(previous);
+        }
+        let summary = OrderSummary {
+            id: envelope.stream.clone(),
+            status: order.status,
+            action: order.action.clone(),
+            delivery_type: order.delivery_type,
+            customer: order.customer.clone(),
+            amount: order.amount,
+            items: order.items.len(),
+            placed_at: previous.as_ref().map_or(time, |previous| previous.placed_at.min(time)),
+            updated_at: previous.as_ref().map_or(time, |previous| previous.updated_at.max(time)),
+        };
+        self.index(&summary);
+        self.orders.insert(summary.id.clone(), summary);
+        self.checkpoint = envelope.position;
+        Ok(())
+    }
+
+    fn checkpoint(&self) -> usize {
+        self.checkpoint
+    }
+
+    fn reset(&mut self) {
+        *self = Self::new();
+    }
+}
+
+fn matches(summary: &OrderSummary, query: &OrderQuery) -> bool {
+    query.status.is_none_or(|status| summary.status == status)
+        && query.action.as_ref().is_none_or(|action| summary.action == *action)
+        && query.delivery_type.is_none_or(|delivery_type| summary.delivery_type == Some(delivery_type))
+        && query.customer.as_ref().is_none_or(|customer| summary.customer.as_ref() == Some(customer))
+        && query.placed.as_ref().is_none_or(|placed| placed.contains(&summary.placed_at))
+}
+
+const fn sort_key(summary: &OrderSummary, sort: OrderSort) -> u32 {
+    match sort {
+        OrderSort::PlacedAt => summary.placed_at,
+        OrderSort::UpdatedAt => summary.updated_at,
+        OrderSort::Amount => summary.amount,
+    }
+}
+
+fn parse_cursor(cursor: &str) -> Result<(u32, OrderId), QueryError> {
+    cursor
+        .split_once(':')
+        .and_then(|(key, id)| Some((key.parse().ok()?, id.to_string())))
+        .ok_or_else(|| QueryError::InvalidCursor(cursor.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::mpsc::channel;
+
+    use super::*;
+    use crate::{
+        entities::ReasonCode,
+        infra::{
+            projection::Projector,
+            store::OrderStore,
+            test_support::{append_all, delivery_failed, pay, sent},
+        },
+    };
+
+    fn store() -> OrderStore {
+        let mut store = OrderStore::new();
+        for (order_id, time) in [("1001", 10), ("1002", 20), ("1003", 30), ("1004", 40)] {
+            pay(&mut store, order_id, if order_id == "1004" { DeliveryType::Ups } else { DeliveryType::Gls }, time + 2);
+            append_all(&mut store, order_id, [sent(order_id, time + 3)]);
+        }
+        for order_id in ["1001", "1003", "1004"] {
+            append_all(&mut store, order_id, [delivery_failed(order_id, ReasonCode::WrongAddress, 100)]);
+        }
+        store
+    }
+
+    #[test]
+    fn filters_by_state_and_action() {
+        let mut list = OrderList::new();
+        Projector::new("order_list", channel().1).run(&mut list, &store()).expect("run failed");
+
+        let query = OrderQuery {
+            status: Some(State::DeliveryFailed),
+            action: Some(Action::ContactCustomer),
+            delivery_type: Some(DeliveryType::Gls),
+            ..OrderQuery::default()
+        };
+        let page = list.query(&query).expect("query failed");
+        assert_eq!(page.orders.iter().map(|summary| summary.id.as_str()).collect::<Vec<_>>(), vec!["1001", "1003"]);
+        assert_eq!(list.get("1002").map(|summary| summary.status), Some(State::Sent));
+
+        let placed = list.query(&OrderQuery { placed: Some(15..35), ..OrderQuery::default() }).expect("query failed");
+        assert_eq!(placed.orders.len(), 2);
+    }
+
+    #[test]
+    fn pages_with_cursor() {
+        let mut list = OrderList::new();
+        Projector::new("order_list", channel().1).run(&mut list, &store()).expect("run failed");
+        let mut query = OrderQuery { customer: Some("765432".to_string()), descending: true, limit: 3, ..OrderQuery::default() };
+
+        let first = list.query(&query).expect("query failed");
+        assert_eq!(first.orders.iter().map(|summary| summary.id.as_str()).collect::<Vec<_>>(), vec!["1004", "1003", "1002"]);
+        query.after = first.next;
+        let second = list.query(&query).expect("query failed");
+        assert_eq!(second.orders.iter().map(|summary| summary.id.as_str()).collect::<Vec<_>>(), vec!["1001"]);
+        assert_eq!(second.next, None);
+
+        query.after = Some("nonsense".to_string());
+        assert_eq!(list.query(&query), Err(QueryError::InvalidCursor("nonsense".to_string())));
+    }
+}
@@ -0,0 +1,115 @@
+use std::sync::mpsc::Sender;
+
+use crate::infra::store::EventStore;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PublishError {
+    Disconnected,
+    Rejected(String),
+}
+
+/// An appended event waiting to be published. `id` is its position in the store's outbox, starting at 1.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OutboxEntry<E> {
+    pub id: usize,
+    pub stream: String,
+    pub version: usize,
+    pub event: E,
+    pub delivered: bool,
+}
+
+/// Where the relay sends events: a message broker in production, a channel in tests.
+pub trait EventPublisher<E> {
+    fn publish(&mut self, entry: &OutboxEntry<E>) -> Result<(), PublishError>;
+}
+
+/// Publishes into an in-process channel.
+#[derive(Debug, Clone)]
+pub struct ChannelPublisher<E> {
+    sender: Sender<OutboxEntry<E>>,
+}
+
+impl<E> ChannelPublisher<E> {
+    pub const fn new(sender: Sender<OutboxEntry<E>>) -> Self {
+        Self { sender }
+    }
+}
+
+impl<E: Clone> EventPublisher<E> for ChannelPublisher<E> {
+    fn publish(&mut self, entry: &OutboxEntry<E>) -> Result<(), PublishError> {
+        self.sender.send(entry.clone()).map_err(|_| PublishError::Disconnected)
+    }
+}
+
+/// Publishes pending outbox entries in order and marks each delivered once the publisher accepts it. Stops at
+/// the first failure so entries are never published out of order; the next run picks up where this one
+/// stopped. Returns the number of entries delivered.
+pub fn relay<E: Clone + Ord>(store: &mut EventStore<E>, publisher: &mut impl EventPublisher<E>) -> Result<usize, PublishError> {
+    let pending: Vec<OutboxEntry<E>> = store.pending().into_iter().cloned().collect();
+    let mut delivered = 0;
+    for entry in pending {
+        publisher.publish(&entry)?;
+        store.mark_delivered(entry.id);
+        delivered += 1;
+    }
+    Ok(delivered)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{entities::OrderEvent, infra::store::OrderStore};
+    use std::sync::mpsc::channel;
+
+    struct FlakyPublisher {
+        failures: usize,
+        published: Vec<usize>,
+    }
+
+    impl EventPublisher<OrderEvent> for FlakyPublisher {
+        fn publish(&mut self, entry: &OutboxEntry<OrderEvent>) -> Result<(), PublishError> {
+            if self.failures > 0 {
+                self.failures -= 1;
+                return Err(PublishError::Rejected("broker unavailable".to_string()));
+            }
+            self.published.push(entry.id);
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn relay_publishes_each_event_once() {
+        let mut store = OrderStore::new();
+        let (sender, receiver) = channel();
+        let mut publisher = ChannelPublisher::new(sender);
+        store
+            .append("1234", OrderEvent::ItemAdded { id: "1234".to_string(), order_id: "1234".to_string(), time: 1 })
+            .expect("append failed");
+        store
+            .append("2345", OrderEvent::ItemAdded { id: "2345".to_string(), order_id: "2345".to_string(), time: 2 })
+            .expect("append failed");
+
+        assert_eq!(relay(&mut store, &mut publisher), Ok(2));
+        assert_eq!(relay(&mut store, &mut publisher), Ok(0));
+        let sent: Vec<_> = receiver.try_iter().map(|entry| (entry.stream, entry.version)).collect();
+        assert_eq!(sent, vec![("1234".to_string(), 1), ("2345".to_string(), 1)]);
+        assert!(store.pending().is_empty());
+    }
+
+    #[test]
+    fn failed_publish_stays_pending() {
+        let mut store = OrderStore::new();
+        let mut publisher = FlakyPublisher { failures: 1, published: Vec::new() };
+        store
+            .append("1234", OrderEvent::ItemAdded { id: "1234".to_string(), order_id: "1234".to_string(), time: 1 })
+            .expect("append failed");
+        store
+            .append("1234", OrderEvent::ItemAdded { id: "2345".to_string(), order_id: "1234".to_string(), time: 2 })
+            .expect("append failed");
+
+        assert!(relay(&mut store, &mut publisher).is_err());
+        assert_eq!(store.pending().len(), 2);
+        assert_eq!(relay(&mut store, &mut publisher), Ok(2));
+        assert_eq!(publisher.published, vec![1, 2]);
+    }
+}
@@ -0,0 +1,299 @@
+use std::collections::HashMap;
+
+use crate::entities::{Action, AuthorizationId, Order, OrderEvent, OrderId, PaymentType, State};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PaymentError {
+    Declined(String),
+    NotPayable(State),
+    UnknownAuthorization(AuthorizationId),
+    InvalidAmount { requested: u32, available: u32 },
+    Voided(AuthorizationId),
+    AlreadyCaptured(AuthorizationId),
+    Unsettled(u32),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Authorization {
+    pub id: AuthorizationId,
+    pub payment_type: PaymentType,
+    pub amount: u32,
+}
+
+/// A payment service provider. Amounts are in the smallest currency unit, as on `OrderPayed`.
+pub trait PaymentGateway {
+    fn authorize(&mut self, order_id: &str, payment_type: PaymentType, amount: u32) -> Result<Authorization, PaymentError>;
+    fn capture(&mut self, authorization_id: &str, amount: u32) -> Result<(), PaymentError>;
+    fn void(&mut self, authorization_id: &str) -> Result<(), PaymentError>;
+    fn refund(&mut self, authorization_id: &str, amount: u32) -> Result<(), PaymentError>;
+    /// Pays back money that was taken without an authorization, such as a bank transfer.
+    fn credit(&mut self, order_id: &str, payment_type: PaymentType, amount: u32) -> Result<(), PaymentError>;
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+struct MockAuthorization {
+    amount: u32,
+    captured: u32,
+    refunded: u32,
+    voided: bool,
+}
+
+/// An in-memory gateway that declines any authorization above `limit` and approves everything else.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MockGateway {
+    limit: u32,
+    next_number: u32,
+    authorizations: HashMap<AuthorizationId, MockAuthorization>,
+    credited: HashMap<OrderId, u32>,
+}
+
+impl MockGateway {
+    pub fn new(limit: u32) -> Self {
+        Self { limit, next_number: 1, authorizations: HashMap::new(), credited: HashMap::new() }
+    }
+
+    fn authorization(&mut self, authorization_id: &str) -> Result<&mut MockAuthorization, PaymentError> {
+        let authorization = self
+            .authorizations
+            .get_mut(authorization_id)
+            .ok_or_else(|| PaymentError::UnknownAuthorization(authorization_id.to_string()))?;
+        if authorization.voided {
+            return Err(PaymentError::Voided(authorization_id.to_string()));
+        }
+        Ok(authorization)
+    }
+}
+
+impl PaymentGateway for MockGateway {
+    fn authorize(&mut self, order_id: &str, payment_type: PaymentType, amount: u32) -> Result<Authorization, PaymentError> {
+        if amount > self.limit {
+            return Err(PaymentError::Declined(format!("{payment_type:?} limit exceeded")));
+        }
+        let id = format!("AUTH-{order_id}-{}", self.next_number);
+        self.next_number += 1;
+        self.authorizations.insert(id.clone(), MockAuthorization { amount, ..MockAuthorization::default() });
+        Ok(Authorization { id, payment_type, amount })
+    }
+
+    fn capture(&mut self, authorization_id: &str, amount: u32) -> Result<(), PaymentError> {
+        let authorization = self.authorization(authorization_id)?;
+        let available = authorization.amount - authorization.captured;
+        if amount > available {
+            return Err(PaymentError::InvalidAmount { requested: amount, available });
+        }
+        authorization.captured += amount;
+        Ok(())
+    }
+
+    fn void(&mut self, authorization_id: &str) -> Result<(), PaymentError> {
+        let authorization = self.authorization(authorization_id)?;
+        if authorization.captured > 0 {
+            return Err(PaymentError::AlreadyCaptured(authorization_id.to_string()));
+        }
+        authorization.voided = true;
+        Ok(())
+    }
+
+    fn refund(&mut self, authorization_id: &str, amount: u32) -> Result<(), PaymentError> {
+        let authorization = self.authorization(authorization_id)?;
+        let available = authorization.captured - authorization.refunded;
+        if amount > available {
+            return Err(PaymentError::InvalidAmount { requested: amount, available });
+        }
+        authorization.refunded += amount;
+        Ok(())
+    }
+
+    fn credit(&mut self, order_id: &str, _payment_type: PaymentType, amount: u32) -> Result<(), PaymentError> {
+        *self.credited.entry(order_id.to_string()).or_default() += amount;
+        Ok(())
+    }
+}
+
+/// How long a card authorization can be captured against, in seconds. Card schemes typically allow seven days.
+pub const AUTHORIZATION_VALIDITY: u32 = 7 * 24 * 60 * 60;
+
+/// Fulfils `Action::Pay` (or the first payment of an order in progress) by authorizing `amount`. The money is
+/// captured later, through `Action::Capture`, before the order is prepared. A declined authorization is not an
+/// error: it becomes a `PaymentDeclined` event so the order keeps asking for payment.
+pub fn pay_order(
+    order: &Order, gateway: &mut impl PaymentGateway, payment_type: PaymentType, amount: u32, time: u32,
+) -> Result<OrderEvent, PaymentError> {
+    if !order.pending_actions.iter().any(|pending| pending.action == Action::Pay)
+        && !matches!(order.status, State::Empty | State::InProgress)
+    {
+        return Err(PaymentError::NotPayable(order.status));
+    }
+    match gateway.authorize(&order.id, payment_type, amount) {
+        Ok(authorization) => Ok(OrderEvent::PaymentAuthorized {
+            order_id: order.id.clone(),
+            authorization_id: authorization.id,
+            payment_type,
+            amount: authorization.amount,
+            expires_at: time + AUTHORIZATION_VALIDITY,
+            time,
+        }),
+        Err(PaymentError::Declined(reason)) => Ok(OrderEvent::PaymentDeclined { order_id: order.id.clone(), payment_type, reason, time }),
+        Err(err) => Err(err),
+    }
+}
+
+/// Fulfils `Action::Capture` by capturing the full outstanding authorization of an `Authorized` order. The
+/// order can only be prepared once this has succeeded.
+pub fn capture_payment(order: &Order, gateway: &mut impl PaymentGateway, time: u32) -> Result<OrderEvent, PaymentError> {
+    match &order.authorization {
+        Some(authorization) if order.status == State::Authorized => {
+            gateway.capture(&authorization.id, authorization.amount)?;
+            Ok(OrderEvent::PaymentCaptured {
+                order_id: order.id.clone(),
+                authorization_id: authorization.id.clone(),
+                amount: authorization.amount,
+                time,
+            })
+        }
+        _ => Err(PaymentError::NotPayable(order.status)),
+    }
+}
+
+/// Fulfils `Action::Refund` by paying back the order's `amount`, charge by charge: money captured on an
+/// authorization is refunded against that authorization, anything else is credited. `OrderRefunded` is only
+/// returned once the gateway has paid all of it back.
+pub fn refund_payment(order: &Order, gateway: &mut impl PaymentGateway, time: u32) -> Result<OrderEvent, PaymentError> {
+    if order.status != State::DeliveryFailed {
+        return Err(PaymentError::NotPayable(order.status));
+    }
+    let mut remaining = order.amount;
+    for charge in &order.charges {
+        let amount = charge.amount.min(remaining);
+        if amount == 0 {
+            break;
+        }
+        match &charge.authorization_id {
+            Some(authorization_id) => gateway.refund(authorization_id, amount)?,
+            None => gateway.credit(&order.id, charge.payment_type, amount)?,
+        }
+        remaining -= amount;
+    }
+    Ok(OrderEvent::OrderRefunded { order_id: order.id.clone(), amount: order.amount, time })
+}
+
+/// Sends an order, capturing its authorization first if that has not happened yet. `OrderSent` is only ever
+/// returned for an order whose payment has been captured.
+pub fn send_order(order: &Order, gateway: &mut impl PaymentGateway, time: u32) -> Result<Vec<OrderEvent>, PaymentError> {
+    let sent = OrderEvent::OrderSent { order_id: order.id.clone(), time };
+    match order.status {
+        State::Payed if order.holds_shipping() => Err(PaymentError::Unsettled(order.unsettled)),
+        State::Payed => Ok(vec![sent]),
+        State::Authorized => Ok(vec![capture_payment(order, gateway, time)?, sent]),
+        status => Err(PaymentError::NotPayable(status)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        entities::{OrderEventDiscriminants, Reason, ReasonCode},
+        logic::{aggregate_order, authorization_expiry, TRANSITIONS},
+    };
+    use fsm::StateMachine;
+    use strum::IntoEnumIterator;
+
+    fn aggregate(events: &[OrderEvent]) -> Order {
+        let mut machine = StateMachine::new(State::iter().collect(), OrderEventDiscriminants::iter().collect(), TRANSITIONS.to_owned());
+        aggregate_order(events.to_vec(), Order::new("1234".to_string()), &mut machine)
+    }
+
+    #[test]
+    fn pay_order_authorizes_or_declines() {
+        let mut events = vec![OrderEvent::ItemAdded { id: "1234".to_string(), order_id: "1234".to_string(), time: 1 }];
+        let mut gateway = MockGateway::new(500);
+
+        let declined = pay_order(&aggregate(&events), &mut gateway, PaymentType::Visa, 600, 2).expect("gateway failed");
+        assert!(matches!(declined, OrderEvent::PaymentDeclined { .. }));
+        events.push(declined);
+        assert_eq!(aggregate(&events).action, Action::Pay);
+
+        events.push(pay_order(&aggregate(&events), &mut gateway, PaymentType::Visa, 345, 3).expect("gateway failed"));
+        let order = aggregate(&events);
+        assert_eq!(order.status, State::Authorized);
+        assert_eq!(order.action, Action::Capture);
+        assert_eq!(order.amount, 0);
+        assert_eq!(pay_order(&order, &mut gateway, PaymentType::Visa, 345, 4), Err(PaymentError::NotPayable(State::Authorized)));
+    }
+
+    #[test]
+    fn send_order_captures_first() {
+        let mut events = vec![OrderEvent::ItemAdded { id: "1234".to_string(), order_id: "1234".to_string(), time: 1 }];
+        let mut gateway = MockGateway::new(500);
+        events.push(pay_order(&aggregate(&events), &mut gateway, PaymentType::Visa, 345, 2).expect("gateway failed"));
+
+        let mut unpaid = events.clone();
+        unpaid.push(OrderEvent::OrderSent { order_id: "1234".to_string(), time: 3 });
+        assert_eq!(aggregate(&unpaid).status, State::Failed);
+
+        let mut captured = events.clone();
+        captured.push(capture_payment(&aggregate(&events), &mut gateway.clone(), 3).expect("capture failed"));
+        assert_eq!(aggregate(&captured).action, Action::PrepareOrder);
+
+        events.extend(send_order(&aggregate(&events), &mut gateway, 3).expect("capture failed"));
+        let order = aggregate(&events);
+        assert_eq!(order.status, State::Sent);
+        assert_eq!(order.amount, 345);
+    }
+
+    #[test]
+    fn authorization_expires_back_to_pay() {
+        let mut events = vec![OrderEvent::ItemAdded { id: "1234".to_string(), order_id: "1234".to_string(), time: 1 }];
+        let mut gateway = MockGateway::new(500);
+        events.push(pay_order(&aggregate(&events), &mut gateway, PaymentType::Visa, 345, 2).expect("gateway failed"));
+        assert_eq!(authorization_expiry(&aggregate(&events), 3), None);
+
+        let expired = authorization_expiry(&aggregate(&events), 2 + AUTHORIZATION_VALIDITY).expect("authorization should expire");
+        events.push(expired);
+        let order = aggregate(&events);
+        assert_eq!(order.status, State::InProgress);
+        assert_eq!(order.action, Action::Pay);
+        assert_eq!(order.authorization, None);
+    }
+
+    #[test]
+    fn refund_pays_back_each_payment_as_it_was_made() {
+        let mut events = vec![
+            OrderEvent::ItemAdded { id: "1234".to_string(), order_id: "1234".to_string(), time: 1 },
+            OrderEvent::OrderPayed { order_id: "1234".to_string(), payment_type: PaymentType::BankTransfer, amount: 300, time: 2 },
+            OrderEvent::PaymentSettled { order_id: "1234".to_string(), amount: 300, time: 3 },
+            OrderEvent::ItemAdded { id: "2345".to_string(), order_id: "1234".to_string(), time: 4 },
+        ];
+        let mut gateway = MockGateway::new(500);
+        events.push(pay_order(&aggregate(&events), &mut gateway, PaymentType::Visa, 45, 5).expect("gateway failed"));
+        events.extend(send_order(&aggregate(&events), &mut gateway, 6).expect("capture failed"));
+        let order = aggregate(&events);
+        assert_eq!(refund_payment(&order, &mut gateway, 7), Err(PaymentError::NotPayable(State::Sent)));
+
+        events.push(OrderEvent::OrderDeliveryFailed {
+            order_id: "1234".to_string(),
+            reason: Reason { reason_code: ReasonCode::Refused, reason_message: String::new() },
+            time: 7,
+        });
+        let refunded = refund_payment(&aggregate(&events), &mut gateway, 8).expect("refund failed");
+        assert_eq!(refunded, OrderEvent::OrderRefunded { order_id: "1234".to_string(), amount: 345, time: 8 });
+        assert_eq!(gateway.credited.get("1234"), Some(&300));
+        assert_eq!(gateway.authorizations.values().map(|authorization| authorization.refunded).collect::<Vec<_>>(), vec![45]);
+        events.push(refunded);
+        assert_eq!((aggregate(&events).status, aggregate(&events).amount), (State::Refunded, 0));
+    }
+
+    #[test]
+    fn mock_gateway_refund_and_void() {
+        let mut gateway = MockGateway::new(500);
+        let captured = gateway.authorize("1234", PaymentType::Mastercard, 300).expect("declined");
+        gateway.capture(&captured.id, 300).expect("capture failed");
+        assert_eq!(gateway.refund(&captured.id, 400), Err(PaymentError::InvalidAmount { requested: 400, available: 300 }));
+        assert_eq!(gateway.refund(&captured.id, 100), Ok(()));
+
+        let voided = gateway.authorize("1234", PaymentType::Mastercard, 300).expect("declined");
+        assert_eq!(gateway.void(&voided.id), Ok(()));
+        assert_eq!(gateway.capture(&voided.id, 300), Err(PaymentError::Voided(voided.id.clone())));
+    }
+}
@@ -0,0 +1,477 @@
+use std::{collections::HashMap, sync::mpsc::Sender};
+
+use fsm::StateMachine;
+use serde::{Deserialize, Serialize};
+use strum::IntoEnumIterator;
+
+use crate::{
+    entities::{Action, Order, OrderEvent, OrderEventDiscriminants, OrderId, State},
+    infra::{
+        carrier::{prepare_order, CarrierAdapter},
+        json_file::{FileError, JsonFile},
+        payment::{capture_payment, refund_payment, PaymentGateway},
+        schema::SchemaError,
+        store::OrderStore,
+        stuck::WorkItem,
+    },
+    logic::{aggregate_order, TRANSITIONS},
+};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ProcessError {
+    /// The handler failed, or there is none for the action, and it will be tried again from `retry_at`.
+    Retrying {
+        order_id: OrderId,
+        action: Action,
+        attempts: u32,
+        retry_at: u32,
+        reason: String,
+    },
+    /// The handler failed `attempts` times; the action is left for a person to deal with.
+    GaveUp {
+        order_id: OrderId,
+        action: Action,
+        attempts: u32,
+        reason: String,
+    },
+    /// The order's stream could not be read or written.
+    Store { order_id: OrderId, error: SchemaError },
+    /// The retry attempts could not be saved.
+    Attempts(FileError),
+}
+
+/// Carries out one or more kinds of `Action` for an order. Returns the events the work produced; the process
+/// manager adds the `ActionCompleted` itself.
+pub trait ActionHandler {
+    fn handle(&mut self, order: &Order, action: &Action, time: u32) -> Result<Vec<OrderEvent>, String>;
+}
+
+impl<F: FnMut(&Order, u32) -> Result<Vec<OrderEvent>, String>> ActionHandler for F {
+    fn handle(&mut self, order: &Order, _action: &Action, time: u32) -> Result<Vec<OrderEvent>, String> {
+        self(order, time)
+    }
+}
+
+/// Handles `PrepareOrder` by booking the shipment with the carrier.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Warehouse<C> {
+    carrier: C,
+}
+
+impl<C: CarrierAdapter> Warehouse<C> {
+    pub const fn new(carrier: C) -> Self {
+        Self { carrier }
+    }
+}
+
+impl<C: CarrierAdapter> ActionHandler for Warehouse<C> {
+    fn handle(&mut self, order: &Order, _action: &Action, time: u32) -> Result<Vec<OrderEvent>, String> {
+        prepare_order(order, &mut self.carrier, time)
+            .map(|event| vec![event])
+            .map_err(|err| format!("{err:?}"))
+    }
+}
+
+/// Handles the actions that move money with the payment gateway: `Capture` and `Refund`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Payments<G> {
+    gateway: G,
+}
+
+impl<G: PaymentGateway> Payments<G> {
+    pub const fn new(gateway: G) -> Self {
+        Self { gateway }
+    }
+}
+
+impl<G: PaymentGateway> ActionHandler for Payments<G> {
+    fn handle(&mut self, order: &Order, action: &Action, time: u32) -> Result<Vec<OrderEvent>, String> {
+        let event = match action {
+            Action::Capture => capture_payment(order, &mut self.gateway, time),
+            Action::Refund => refund_payment(order, &mut self.gateway, time),
+            action => return Err(format!("payments cannot handle {action:?}")),
+        };
+        event.map(|event| vec![event]).map_err(|err| format!("{err:?}"))
+    }
+}
+
+/// Handles the actions that need a person, `ContactCustomer`, `RefundDiff` and `CheckOrder`, by handing each to
+/// customer service as a work item. The action is done once the work item is queued; what the person does about
+/// it comes back as events of its own.
+#[derive(Debug, Clone)]
+pub struct CustomerService {
+    queue: Sender<WorkItem>,
+}
+
+impl CustomerService {
+    pub const fn new(queue: Sender<WorkItem>) -> Self {
+        Self { queue }
+    }
+}
+
+impl ActionHandler for CustomerService {
+    fn handle(&mut self, order: &Order, action: &Action, time: u32) -> Result<Vec<OrderEvent>, String> {
+        let failure = order
+            .delivery_failure
+            .as_ref()
+            .map(|failure| format!(" after {:?}: {}", failure.reason_code, failure.reason_message))
+            .unwrap_or_default();
+        let reason = format!("{:?}{failure}", order.status);
+        self.queue
+            .send(WorkItem { order_id: order.id.clone(), action: action.clone(), reason, raised_at: time })
+            .map(|()| Vec::new())
+            .map_err(|_| "customer service queue is closed".to_string())
+    }
+}
+
+/// Exponential backoff between attempts, in seconds: `base_delay`, twice that, and so on up to `max_delay`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct RetryPolicy {
+    pub max_attempts: u32,
+    pub base_delay: u32,
+    pub max_delay: u32,
+}
+
+impl RetryPolicy {
+    fn delay(&self, attempts: u32) -> u32 {
+        self.base_delay.saturating_mul(2u32.saturating_pow(attempts.saturating_sub(1))).min(self.max_delay)
+    }
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self { max_attempts: 5, base_delay: 60, max_delay: 60 * 60 }
+    }
+}
+
+/// The failed attempts at one action of one order. `retry_at` is `None` once the manager has given up.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Attempts {
+    pub order_id: OrderId,
+    pub action: Action,
+    pub count: u32,
+    pub retry_at: Option<u32>,
+}
+
+/// Where retry attempts are kept between restarts, so a restart neither skips a backoff nor revives an action
+/// that was given up on.
+pub trait AttemptStore {
+    fn load(&self) -> Result<Vec<Attempts>, FileError>;
+    fn save(&mut self, attempts: &[Attempts]) -> Result<(), FileError>;
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FileAttemptStore {
+    file: JsonFile,
+}
+
+impl FileAttemptStore {
+    pub fn new(path: impl Into<std::path::PathBuf>) -> Self {
+        Self { file: JsonFile::new(path) }
+    }
+}
+
+impl AttemptStore for FileAttemptStore {
+    fn load(&self) -> Result<Vec<Attempts>, FileError> {
+        self.file.load()
+    }
+
+    fn save(&mut self, attempts: &[Attempts]) -> Result<(), FileError> {
+        self.file.save(attempts)
+    }
+}
+
+/// Whether the order is in a state the action can be carried out in. Later events can overtake an action that
+/// is still pending, and the actions on the customer's side, like `Pay`, are never carried out for them.
+fn ready(order: &Order, action: &Action) -> bool {
+    match action {
+        Action::None | Action::AddItem | Action::DeleteItem | Action::Pay => false,
+        Action::Capture => order.status == State::Authorized && order.authorization.is_some(),
+        Action::PrepareOrder => matches!(order.status, State::Payed | State::PartiallySent) && !order.holds_shipping(),
+        Action::RefundDiff => order.status == State::Payed && order.amount > 0,
+        Action::ContactCustomer | Action::Refund => order.status == State::DeliveryFailed,
+        Action::CheckOrder => true,
+    }
+}
+
+/// Dispatches each of an order's `pending_actions` to the handler registered for it and records the outcome.
+pub struct ProcessManager<S> {
+    handlers: Vec<Box<dyn ActionHandler>>,
+    /// The index in `handlers` of the handler for each action.
+    routes: HashMap<Action, usize>,
+    attempts: Vec<Attempts>,
+    store: S,
+    policy: RetryPolicy,
+}
+
+impl<S: AttemptStore> ProcessManager<S> {
+    pub fn new(store: S, policy: RetryPolicy) -> Result<Self, FileError> {
+        Ok(Self { handlers: Vec::new(), routes: HashMap::new(), attempts: store.load()?, store, policy })
+    }
+
+    /// Registers one handler for all of `actions`, replacing any they had.
+    pub fn register(&mut self, actions: &[Action], handler: impl ActionHandler + 'static) {
+        self.handlers.push(Box::new(handler));
+        for action in actions {
+            self.routes.insert(action.clone(), self.handlers.len() - 1);
+        }
+    }
+
+    /// Runs the handler for one of the order's pending actions, unless it is backing off or has given up. On
+    /// success returns the handler's events followed by `ActionCompleted`. An action without a handler backs
+    /// off like a failed one, so it is reported once per attempt rather than on every run.
+    pub fn dispatch(&mut self, order: &Order, action: &Action, now: u32) -> Result<Vec<OrderEvent>, ProcessError> {
+        if *action == Action::None {
+            return Ok(Vec::new());
+        }
+        let index = self
+            .attempts
+            .iter()
+            .position(|attempts| attempts.order_id == order.id && attempts.action == *action);
+        match index.map(|index| self.attempts[index].retry_at) {
+            Some(Some(retry_at)) if retry_at > now => return Ok(Vec::new()),
+            Some(None) => return Ok(Vec::new()),
+            _ => {}
+        }
+        let result = match self.routes.get(action) {
+            Some(handler) => self.handlers[*handler].handle(order, action, now),
+            None => Err("no handler".to_string()),
+        };
+        let count = index.map_or(0, |index| self.attempts.remove(index).count) + 1;
+        let outcome = match result {
+            Ok(mut events) => {
+                events.push(OrderEvent::ActionCompleted { order_id: order.id.clone(), action: action.clone(), time: now });
+                Ok(events)
+            }
+            Err(reason) if count >= self.policy.max_attempts => {
+                self.attempts
+                    .push(Attempts { order_id: order.id.clone(), action: action.clone(), count, retry_at: None });
+                Err(ProcessError::GaveUp { order_id: order.id.clone(), action: action.clone(), attempts: count, reason })
+            }
+            Err(reason) => {
+                let retry_at = now + self.policy.delay(count);
+                self.attempts
+                    .push(Attempts { order_id: order.id.clone(), action: action.clone(), count, retry_at: Some(retry_at) });
+                Err(ProcessError::Retrying { order_id: order.id.clone(), action: action.clone(), attempts: count, retry_at, reason })
+            }
+        };
+        if index.is_some() || outcome.is_err() {
+            self.store.save(&self.attempts).map_err(ProcessError::Attempts)?;
+        }
+        outcome
+    }
+
+    /// Dispatches the pending actions of every order in the store, oldest first, and appends what the handlers
+    /// produced. Each order is replayed once; the events of a successful handler are applied to it, so later
+    /// handlers see the earlier work, including actions it raised.
+    pub fn run(&mut self, store: &mut OrderStore, now: u32) -> Vec<ProcessError> {
+        let mut errors = Vec::new();
+        for stream in store.streams() {
+            if let Err(error) = self.run_order(store, &stream, now, &mut errors) {
+                errors.push(ProcessError::Store { order_id: stream, error });
+            }
+        }
+        errors
+    }
+
+    fn run_order(&mut self, store: &mut OrderStore, stream: &str, now: u32, errors: &mut Vec<ProcessError>) -> Result<(), SchemaError> {
+        let mut machine = StateMachine::new(State::iter().collect(), OrderEventDiscriminants::iter().collect(), TRANSITIONS.to_owned());
+        let mut order = aggregate_order(store.load(stream)?, Order::new(stream.to_string()), &mut machine);
+        let mut tried = Vec::new();
+        while let Some(action) = order
+            .pending_actions
+            .iter()
+            .map(|pending| pending.action.clone())
+            .find(|action| !tried.contains(action))
+        {
+            tried.push(action.clone());
+            if !ready(&order, &action) {
+                continue;
+            }
+            match self.dispatch(&order, &action, now) {
+                Ok(events) => {
+                    for event in &events {
+                        store.append(stream, event.clone())?;
+                    }
+                    order = aggregate_order(events, order, &mut machine);
+                }
+                Err(err) => errors.push(err),
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, path::PathBuf, sync::mpsc::channel};
+
+    use super::*;
+    use crate::{
+        entities::{DeliveryResolution, DeliveryType, PaymentType, ReasonCode},
+        infra::{
+            carrier::MockCarrier,
+            payment::{pay_order, MockGateway},
+            test_support::{append_all, delivery_failed, load, pay, place, sent},
+        },
+    };
+
+    fn attempts_file(name: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("process_attempts_{name}_{}.json", std::process::id()));
+        let _ = fs::remove_file(&path);
+        path
+    }
+
+    fn manager(path: &PathBuf, policy: RetryPolicy) -> ProcessManager<FileAttemptStore> {
+        ProcessManager::new(FileAttemptStore::new(path), policy).expect("loading attempts failed")
+    }
+
+    #[test]
+    fn dispatches_prepare_order_to_warehouse() {
+        let path = attempts_file("warehouse");
+        let mut store = OrderStore::new();
+        pay(&mut store, "1234", DeliveryType::Gls, 3);
+        let mut manager = manager(&path, RetryPolicy::default());
+        manager.register(&[Action::PrepareOrder], Warehouse::new(MockCarrier::gls()));
+
+        assert!(manager.run(&mut store, 10).is_empty());
+        let order = load(&store, "1234");
+        assert_eq!(order.status, State::Sent);
+        assert_eq!(order.action, Action::None);
+        assert!(matches!(
+            store.load("1234").expect("loading failed").last(),
+            Some(OrderEvent::ActionCompleted { action: Action::PrepareOrder, .. })
+        ));
+
+        // Nothing left to do.
+        assert!(manager.run(&mut store, 20).is_empty());
+        assert_eq!(store.load("1234").expect("loading failed").len(), 5);
+    }
+
+    #[test]
+    fn captures_then_prepares_in_one_run_and_refunds_on_request() {
+        let path = attempts_file("payments");
+        let mut store = OrderStore::new();
+        place(&mut store, "1234", DeliveryType::Gls, 1);
+        let mut gateway = MockGateway::new(500);
+        let authorized = pay_order(&load(&store, "1234"), &mut gateway, PaymentType::Visa, 100, 3).expect("gateway failed");
+        store.append("1234", authorized).expect("append failed");
+        let mut manager = manager(&path, RetryPolicy::default());
+        manager.register(&[Action::Capture, Action::Refund], Payments::new(gateway));
+        manager.register(&[Action::PrepareOrder], Warehouse::new(MockCarrier::gls()));
+        let (queue, _work) = channel();
+        manager.register(&[Action::ContactCustomer], CustomerService::new(queue));
+
+        assert!(manager.run(&mut store, 10).is_empty());
+        let order = load(&store, "1234");
+        assert_eq!((order.status, order.amount), (State::Sent, 100));
+        assert!(order.pending_actions.is_empty());
+
+        append_all(&mut store, "1234", [delivery_failed("1234", ReasonCode::Refused, 20)]);
+        assert!(manager.run(&mut store, 21).is_empty());
+        store
+            .append(
+                "1234",
+                OrderEvent::CustomerResponded { order_id: "1234".to_string(), resolution: DeliveryResolution::Refund, time: 22 },
+            )
+            .expect("append failed");
+        assert_eq!(load(&store, "1234").action, Action::Refund);
+        assert!(manager.run(&mut store, 30).is_empty());
+        let order = load(&store, "1234");
+        assert_eq!((order.status, order.amount), (State::Refunded, 0));
+        assert!(order.pending_actions.is_empty());
+    }
+
+    #[test]
+    fn refunds_payments_taken_without_authorization() {
+        let path = attempts_file("refunds");
+        let mut store = OrderStore::new();
+        pay(&mut store, "1234", DeliveryType::Gls, 3);
+        append_all(
+            &mut store,
+            "1234",
+            [
+                sent("1234", 4),
+                delivery_failed("1234", ReasonCode::Refused, 5),
+                OrderEvent::CustomerResponded { order_id: "1234".to_string(), resolution: DeliveryResolution::Refund, time: 6 },
+            ],
+        );
+        let mut manager = manager(&path, RetryPolicy::default());
+        manager.register(&[Action::Refund], Payments::new(MockGateway::new(500)));
+        let (queue, _work) = channel();
+        manager.register(&[Action::ContactCustomer], CustomerService::new(queue));
+
+        assert!(manager.run(&mut store, 10).is_empty());
+        let order = load(&store, "1234");
+        assert_eq!((order.status, order.amount), (State::Refunded, 0));
+        assert!(order.pending_actions.is_empty());
+    }
+
+    #[test]
+    fn hands_people_work_to_customer_service_and_waits_for_customers() {
+        let path = attempts_file("customer_service");
+        let mut store = OrderStore::new();
+        for (order_id, reason_code) in [("1001", ReasonCode::Refused), ("1002", ReasonCode::CustomsHold)] {
+            pay(&mut store, order_id, DeliveryType::Gls, 3);
+            append_all(&mut store, order_id, [sent(order_id, 4), delivery_failed(order_id, reason_code, 5)]);
+        }
+        // Waiting for the customer to pay.
+        place(&mut store, "1003", DeliveryType::Gls, 1);
+        let declined = OrderEvent::PaymentDeclined {
+            order_id: "1003".to_string(),
+            payment_type: PaymentType::Visa,
+            reason: "limit exceeded".to_string(),
+            time: 3,
+        };
+        store.append("1003", declined).expect("append failed");
+        let (queue, work_items) = channel();
+        let mut manager = manager(&path, RetryPolicy { max_attempts: 3, base_delay: 10, max_delay: 15 });
+        manager.register(&[Action::ContactCustomer], CustomerService::new(queue));
+
+        let no_handler = ProcessError::Retrying {
+            order_id: "1002".to_string(),
+            action: Action::CheckOrder,
+            attempts: 1,
+            retry_at: 110,
+            reason: "no handler".to_string(),
+        };
+        assert_eq!(manager.run(&mut store, 100), vec![no_handler]);
+        let item = work_items.try_recv().expect("no work item");
+        assert_eq!((item.order_id.as_str(), item.action), ("1001", Action::ContactCustomer));
+        assert!(load(&store, "1001").pending_actions.is_empty());
+        assert_eq!(load(&store, "1003").action, Action::Pay);
+
+        assert!(manager.run(&mut store, 105).is_empty());
+        assert!(work_items.try_recv().is_err());
+        let _ = fs::remove_file(&path);
+    }
+
+    #[test]
+    fn retries_with_backoff_then_gives_up_across_restarts() {
+        let path = attempts_file("retries");
+        let mut store = OrderStore::new();
+        pay(&mut store, "1234", DeliveryType::Gls, 3);
+        let policy = RetryPolicy { max_attempts: 3, base_delay: 10, max_delay: 15 };
+        let offline = |path: &PathBuf| {
+            let mut manager = manager(path, policy);
+            manager.register(&[Action::PrepareOrder], |_: &Order, _| Err("warehouse offline".to_string()));
+            manager
+        };
+
+        let retrying = |attempts, retry_at| ProcessError::Retrying {
+            order_id: "1234".to_string(),
+            action: Action::PrepareOrder,
+            attempts,
+            retry_at,
+            reason: "warehouse offline".to_string(),
+        };
+        assert_eq!(offline(&path).run(&mut store, 100), vec![retrying(1, 110)]);
+        assert!(offline(&path).run(&mut store, 105).is_empty());
+        let mut manager = offline(&path);
+        assert_eq!(manager.run(&mut store, 110), vec![retrying(2, 125)]);
+        assert!(matches!(manager.run(&mut store, 125)[..], [ProcessError::GaveUp { attempts: 3, .. }]));
+        assert!(offline(&path).run(&mut store, 1_000).is_empty());
+        assert_eq!(store.load("1234").expect("loading failed").len(), 3);
+        let _ = fs::remove_file(&path);
+    }
+}
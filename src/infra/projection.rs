@@ -0,0 +1,265 @@
+use std::{
+    collections::{hash_map::Entry, HashMap},
+    sync::mpsc::Receiver,
+};
+
+use fsm::StateMachine;
+use strum::IntoEnumIterator;
+
+use crate::{
+    entities::{Action, Order, OrderEvent, OrderEventDiscriminants, State},
+    infra::{
+        json_file::FileError,
+        outbox::OutboxEntry,
+        store::OrderStore,
+        subscription::{CheckpointStore, EventEnvelope, LocalCheckpoints, Subscription, SubscriptionError},
+    },
+    logic::{aggregate_order, TRANSITIONS},
+};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ProjectionError {
+    Handler {
+        projection: String,
+        position: usize,
+        reason: String,
+    },
+    Checkpoint {
+        projection: String,
+        error: FileError,
+    },
+}
+
+/// A query-optimized view of the order events, kept up to date by a `Projector`.
+pub trait Projection {
+    fn name(&self) -> &'static str;
+
+    /// Applies one event, given the order as it stood right after it. Events arrive in global order, but a
+    /// failed run is retried, so handling an event at or before `checkpoint` again must not change the view.
+    fn handle(&mut self, envelope: &EventEnvelope<OrderEvent>, order: &Order) -> Result<(), String>;
+
+    /// The global position of the last event applied. Kept with the view so the two never disagree.
+    fn checkpoint(&self) -> usize;
+
+    /// Empties the view and its checkpoint, ready for a rebuild.
+    fn reset(&mut self);
+}
+
+/// How far a projection is behind the event log.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ProjectionLag {
+    pub projection: String,
+    pub checkpoint: usize,
+    pub head: usize,
+    pub behind: usize,
+}
+
+/// Feeds a projection from the log through a `Subscription`, resuming from the subscriber's checkpoint in `C`.
+/// Orders are aggregated one event at a time and kept between runs; an order first seen after a restart is
+/// rebuilt from its stream. A projection that is behind the checkpoint, such as a view that was not kept over the
+/// restart, gets the whole log replayed, but is only handed the events after its own checkpoint.
+pub struct Projector<C = LocalCheckpoints> {
+    subscription: Subscription<OrderEvent>,
+    checkpoints: C,
+    orders: HashMap<String, (Order, StateMachine<State, OrderEventDiscriminants, Action>)>,
+    /// The global position of the last event applied to `orders`, so a redelivered event is not applied twice.
+    applied: usize,
+}
+
+impl Projector<LocalCheckpoints> {
+    /// A projector that keeps its checkpoint in memory, and so reads the log from the beginning on every start.
+    pub fn new(subscriber: &str, live: Receiver<OutboxEntry<OrderEvent>>) -> Self {
+        Self::with_checkpoints(subscriber, LocalCheckpoints::new(), live)
+    }
+}
+
+impl<C: CheckpointStore> Projector<C> {
+    pub fn with_checkpoints(subscriber: &str, checkpoints: C, live: Receiver<OutboxEntry<OrderEvent>>) -> Self {
+        let subscription = Subscription::new(subscriber, &checkpoints, live);
+        let applied = subscription.position();
+        Self { subscription, checkpoints, orders: HashMap::new(), applied }
+    }
+
+    /// Feeds the projection every event after its checkpoint and returns how many it handled.
+    pub fn run(&mut self, projection: &mut dyn Projection, store: &OrderStore) -> Result<usize, ProjectionError> {
+        let name = projection.name();
+        let checkpoint = projection.checkpoint();
+        if checkpoint < self.subscription.position() {
+            self.rewind(name)?;
+        }
+        let (orders, applied) = (&mut self.orders, &mut self.applied);
+        let mut handled = 0;
+        self.subscription
+            .poll(store, &mut self.checkpoints, |envelope| {
+                let (order, machine) = match orders.entry(envelope.stream.clone()) {
+                    Entry::Occupied(entry) => entry.into_mut(),
+                    Entry::Vacant(entry) => {
+                        let earlier = store.load(&envelope.stream).map_err(|err| format!("{err:?}"))?;
+                        let mut machine =
+                            StateMachine::new(State::iter().collect(), OrderEventDiscriminants::iter().collect(), TRANSITIONS.to_owned());
+                        let earlier = earlier.into_iter().take(envelope.version - 1).collect();
+                        let order = aggregate_order(earlier, Order::new(envelope.stream.clone()), &mut machine);
+                        entry.insert((order, machine))
+                    }
+                };
+                if envelope.position > *applied {
+                    *order = aggregate_order(vec![envelope.event.clone()], order.clone(), machine);
+                    *applied = envelope.position;
+                }
+                if envelope.position > checkpoint {
+                    projection.handle(envelope, order)?;
+                    handled += 1;
+                }
+                Ok(())
+            })
+            .map_err(|err| projection_error(name, err))?;
+        Ok(handled)
+    }
+
+    /// Throws a projection away and replays the whole log into it.
+    pub fn rebuild(&mut self, projection: &mut dyn Projection, store: &OrderStore) -> Result<usize, ProjectionError> {
+        projection.reset();
+        self.run(projection, store)
+    }
+
+    fn rewind(&mut self, name: &str) -> Result<(), ProjectionError> {
+        self.orders.clear();
+        self.applied = 0;
+        self.subscription.rewind(&mut self.checkpoints).map_err(|err| projection_error(name, err))
+    }
+}
+
+fn projection_error(name: &str, err: SubscriptionError) -> ProjectionError {
+    match err {
+        SubscriptionError::Handler { position, reason, .. } => ProjectionError::Handler { projection: name.to_string(), position, reason },
+        SubscriptionError::Checkpoint { error, .. } => ProjectionError::Checkpoint { projection: name.to_string(), error },
+    }
+}
+
+pub fn lag(projection: &dyn Projection, store: &OrderStore) -> ProjectionLag {
+    let head = store.head();
+    ProjectionLag {
+        projection: projection.name().to_string(),
+        checkpoint: projection.checkpoint(),
+        head,
+        behind: head.saturating_sub(projection.checkpoint()),
+    }
+}
+
+/// Runs every projection with its projector and reports how far each is behind afterwards. A failing projection
+/// does not hold the others back; its error is in its report.
+pub fn run_all<C: CheckpointStore>(
+    projections: &mut [(&mut Projector<C>, &mut dyn Projection)], store: &OrderStore,
+) -> Vec<(ProjectionLag, Option<ProjectionError>)> {
+    projections
+        .iter_mut()
+        .map(|(projector, projection)| {
+            let error = projector.run(*projection, store).err();
+            (lag(*projection, store), error)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, sync::mpsc::channel};
+
+    use super::*;
+    use crate::infra::subscription::FileCheckpoints;
+
+    #[derive(Default)]
+    struct OrderStates {
+        states: HashMap<String, (State, usize)>,
+        checkpoint: usize,
+        fail_at: Option<usize>,
+    }
+
+    impl Projection for OrderStates {
+        fn name(&self) -> &'static str {
+            "order_states"
+        }
+
+        fn handle(&mut self, envelope: &EventEnvelope<OrderEvent>, order: &Order) -> Result<(), String> {
+            if self.fail_at == Some(envelope.position) {
+                return Err("view unavailable".to_string());
+            }
+            self.states.insert(order.id.clone(), (order.status, order.items.len()));
+            self.checkpoint = envelope.position;
+            Ok(())
+        }
+
+        fn checkpoint(&self) -> usize {
+            self.checkpoint
+        }
+
+        fn reset(&mut self) {
+            self.states.clear();
+            self.checkpoint = 0;
+        }
+    }
+
+    fn item(order_id: &str, time: u32) -> OrderEvent {
+        OrderEvent::ItemAdded { id: format!("{order_id}-{time}"), order_id: order_id.to_string(), time }
+    }
+
+    #[test]
+    fn run_resumes_and_reports_lag() {
+        let mut store = OrderStore::new();
+        let mut projection = OrderStates { fail_at: Some(3), ..OrderStates::default() };
+        store.append("1234", item("1234", 1)).expect("append failed");
+        store.append("2345", item("2345", 2)).expect("append failed");
+        store
+            .append("1234", OrderEvent::OrderSent { order_id: "1234".to_string(), time: 3 })
+            .expect("append failed");
+
+        let mut projector = Projector::new("order_states", channel().1);
+        let reports = run_all(&mut [(&mut projector, &mut projection)], &store);
+        assert_eq!(reports[0].0, ProjectionLag { projection: "order_states".to_string(), checkpoint: 2, head: 3, behind: 1 });
+        assert!(reports[0].1.is_some());
+
+        projection.fail_at = None;
+        assert_eq!(projector.run(&mut projection, &store), Ok(1));
+        assert_eq!(projection.states["1234"], (State::Failed, 1));
+        assert_eq!(projection.states["2345"], (State::InProgress, 1));
+        assert_eq!(lag(&projection, &store).behind, 0);
+    }
+
+    #[test]
+    fn rebuild_replays_everything() {
+        let mut store = OrderStore::new();
+        let mut projection = OrderStates::default();
+        store.append("1234", item("1234", 1)).expect("append failed");
+        let mut projector = Projector::new("order_states", channel().1);
+        projector.run(&mut projection, &store).expect("run failed");
+        projection.states.insert("stale".to_string(), (State::Failed, 0));
+
+        assert_eq!(projector.rebuild(&mut projection, &store), Ok(1));
+        assert_eq!(projection.states.len(), 1);
+        assert_eq!(projection.checkpoint(), 1);
+    }
+
+    #[test]
+    fn resumes_from_file_checkpoints_after_restart() {
+        let path = std::env::temp_dir().join(format!("projector_checkpoints_{}.json", std::process::id()));
+        let _ = fs::remove_file(&path);
+        let open = || FileCheckpoints::open(&path).expect("loading checkpoints failed");
+        let mut store = OrderStore::new();
+        let mut projection = OrderStates::default();
+        store.append("1234", item("1234", 1)).expect("append failed");
+        store.append("2345", item("2345", 2)).expect("append failed");
+        assert_eq!(Projector::with_checkpoints("order_states", open(), channel().1).run(&mut projection, &store), Ok(2));
+
+        store
+            .append("1234", OrderEvent::OrderSent { order_id: "1234".to_string(), time: 3 })
+            .expect("append failed");
+        let mut restarted = Projector::with_checkpoints("order_states", open(), channel().1);
+        assert_eq!(restarted.run(&mut projection, &store), Ok(1));
+        assert_eq!(projection.states["1234"], (State::Failed, 1));
+
+        // A view that was not kept over the restart is replayed from the start.
+        let mut fresh = OrderStates::default();
+        assert_eq!(Projector::with_checkpoints("order_states", open(), channel().1).run(&mut fresh, &store), Ok(3));
+        assert_eq!(fresh.states, projection.states);
+        let _ = fs::remove_file(&path);
+    }
+}
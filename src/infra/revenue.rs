@@ -0,0 +1,209 @@
+use std::collections::BTreeMap;
+
+use crate::{
+    entities::{CountryCode, Order, OrderEvent, PaymentType},
+    infra::{projection::Projection, subscription::EventEnvelope},
+};
+
+const DAY: u32 = 24 * 60 * 60;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Granularity {
+    Daily,
+    /// ISO weeks, starting on Monday.
+    Weekly,
+    Monthly,
+}
+
+/// Revenue of one period for one payment type and delivery country. `period` is the first day of the period
+/// as `YYYY-MM-DD`, or `YYYY-MM` for months. Orders without a delivery address have no `country`. `amount` is
+/// net of refunds, so a period with more refunded than taken is negative.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RevenueRow {
+    pub period: String,
+    pub payment_type: PaymentType,
+    pub country: Option<CountryCode>,
+    pub amount: i64,
+    pub payments: u32,
+    pub refunds: u32,
+}
+
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+struct Totals {
+    amount: i64,
+    payments: u32,
+    refunds: u32,
+}
+
+/// Money taken, per UTC day, payment type and delivery country. Money is taken by `OrderPayed`, or by
+/// `PaymentCaptured` for payments that were authorized first, and given back by `OrderRefunded` on the day of
+/// the refund.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RevenueReport {
+    days: BTreeMap<(u32, PaymentType, Option<CountryCode>), Totals>,
+    checkpoint: usize,
+}
+
+impl RevenueReport {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn rows(&self, granularity: Granularity) -> Vec<RevenueRow> {
+        let mut periods: BTreeMap<(String, PaymentType, Option<CountryCode>), Totals> = BTreeMap::new();
+        for ((day, payment_type, country), totals) in &self.days {
+            let period = periods.entry((period(*day, granularity), *payment_type, *country)).or_default();
+            period.amount += totals.amount;
+            period.payments += totals.payments;
+            period.refunds += totals.refunds;
+        }
+        periods
+            .into_iter()
+            .map(|((period, payment_type, country), totals)| RevenueRow {
+                period,
+                payment_type,
+                country,
+                amount: totals.amount,
+                payments: totals.payments,
+                refunds: totals.refunds,
+            })
+            .collect()
+    }
+
+    /// The report as CSV for finance, with a header row. Amounts are in the smallest currency unit.
+    pub fn to_csv(&self, granularity: Granularity) -> String {
+        self.rows(granularity)
+            .iter()
+            .fold("period,payment_type,country,amount,payments,refunds\n".to_string(), |csv, row| {
+                let country = row.country.map(|country| format!("{country:?}").to_uppercase()).unwrap_or_default();
+                csv + &format!("{},{:?},{},{},{},{}\n", row.period, row.payment_type, country, row.amount, row.payments, row.refunds)
+            })
+    }
+
+    fn add(&mut self, time: u32, payment_type: PaymentType, country: Option<CountryCode>, amount: u32) {
+        let totals = self.days.entry((time / DAY, payment_type, country)).or_default();
+        totals.amount += i64::from(amount);
+        totals.payments += 1;
+    }
+
+    fn refund(&mut self, time: u32, payment_type: PaymentType, country: Option<CountryCode>, amount: u32) {
+        let totals = self.days.entry((time / DAY, payment_type, country)).or_default();
+        totals.amount -= i64::from(amount);
+        totals.refunds += 1;
+    }
+}
+
+impl Projection for RevenueReport {
+    fn name(&self) -> &'static str {
+        "revenue"
+    }
+
+    fn handle(&mut self, envelope: &EventEnvelope<OrderEvent>, order: &Order) -> Result<(), String> {
+        if envelope.position <= self.checkpoint {
+            return Ok(());
+        }
+        self.checkpoint = envelope.position;
+        let country = order.address.as_ref().map(|address| address.country);
+        match &envelope.event {
+            OrderEvent::OrderPayed { payment_type, amount, time, .. } => self.add(*time, *payment_type, country, *amount),
+            OrderEvent::PaymentCaptured { amount, time, .. } => {
+                let payment_type = order.payment_type.ok_or_else(|| format!("captured order {} has no payment type", order.id))?;
+                self.add(*time, payment_type, country, *amount);
+            }
+            OrderEvent::OrderRefunded { amount, time, .. } => {
+                let payment_type = order.payment_type.ok_or_else(|| format!("refunded order {} has no payment type", order.id))?;
+                self.refund(*time, payment_type, country, *amount);
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    fn checkpoint(&self) -> usize {
+        self.checkpoint
+    }
+
+    fn reset(&mut self) {
+        *self = Self::new();
+    }
+}
+
+fn period(day: u32, granularity: Granularity) -> String {
+    match granularity {
+        Granularity::Daily => date(day),
+        // 1970-01-01 was a Thursday, three days into its week.
+        Granularity::Weekly => date(day.saturating_sub((day + 3) % 7)),
+        Granularity::Monthly => date(day)[..7].to_string(),
+    }
+}
+
+/// Formats days since 1970-01-01 as `YYYY-MM-DD`, after Howard Hinnant's `civil_from_days`.
+fn date(day: u32) -> String {
+    let shifted = day + 719_468;
+    let era = shifted / 146_097;
+    let day_of_era = shifted - era * 146_097;
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let shifted_month = (5 * day_of_year + 2) / 153;
+    let day_of_month = day_of_year - (153 * shifted_month + 2) / 5 + 1;
+    let month = if shifted_month < 10 { shifted_month + 3 } else { shifted_month - 9 };
+    let year = year_of_era + era * 400 + u32::from(month <= 2);
+    format!("{year:04}-{month:02}-{day_of_month:02}")
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::mpsc::channel;
+
+    use super::*;
+    use crate::{
+        entities::{Address, DeliveryType, Pii},
+        infra::{
+            projection::Projector,
+            store::OrderStore,
+            test_support::{append_all, payed, CUSTOMER},
+        },
+    };
+
+    fn pay(store: &mut OrderStore, order_id: &str, country: CountryCode, payment_type: PaymentType, amount: u32, time: u32) {
+        let details = OrderEvent::OrderDetailsAdded {
+            order_id: order_id.to_string(),
+            delivery_type: DeliveryType::Gls,
+            delivery_address: Some(Pii::Plain(Address { street: "Taagevej".to_string(), house_number: 43, zip: 4600, country })),
+            customer: CUSTOMER.to_string(),
+            time: time - 1,
+        };
+        append_all(store, order_id, [details, payed(order_id, payment_type, amount, time)]);
+    }
+
+    #[test]
+    fn groups_by_period_payment_type_and_country() {
+        let mut store = OrderStore::new();
+        // Wednesday 2024-01-31, Thursday 2024-02-01 and Monday 2024-02-05, at noon.
+        pay(&mut store, "1001", CountryCode::Dk, PaymentType::Visa, 100, 1_706_702_400);
+        pay(&mut store, "1002", CountryCode::Dk, PaymentType::Visa, 200, 1_706_788_800);
+        pay(&mut store, "1003", CountryCode::De, PaymentType::MobilePay, 300, 1_706_788_800);
+        pay(&mut store, "1004", CountryCode::Dk, PaymentType::Visa, 400, 1_707_134_400);
+        // Part of the first order is refunded on the Monday, and counts against that day.
+        append_all(&mut store, "1001", [OrderEvent::OrderRefunded { order_id: "1001".to_string(), amount: 30, time: 1_707_134_400 }]);
+        let mut revenue = RevenueReport::new();
+        Projector::new("revenue", channel().1).run(&mut revenue, &store).expect("run failed");
+
+        let weekly = revenue.rows(Granularity::Weekly);
+        let visa: Vec<_> = weekly
+            .iter()
+            .filter(|row| row.payment_type == PaymentType::Visa)
+            .map(|row| (row.period.as_str(), row.amount, row.payments))
+            .collect();
+        assert_eq!(visa, vec![("2024-01-29", 300, 2), ("2024-02-05", 370, 1)]);
+        assert_eq!(revenue.rows(Granularity::Daily).len(), 4);
+
+        assert_eq!(
+            revenue.to_csv(Granularity::Monthly),
+            "period,payment_type,country,amount,payments,refunds\n\
+             2024-01,Visa,DK,100,1,0\n\
+             2024-02,Visa,DK,570,2,1\n\
+             2024-02,MobilePay,DE,300,1,0\n"
+        );
+    }
+}
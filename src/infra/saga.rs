@@ -0,0 +1,296 @@
+use std::path::PathBuf;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    entities::{Action, DeliveryResolution, Order, OrderEvent, OrderId, State},
+    infra::{
+        json_file::{FileError, JsonFile},
+        timer::Clock,
+    },
+};
+
+/// How long customer service has to reach the customer after a failed delivery, in seconds.
+pub const CUSTOMER_CONTACT_TIMEOUT: u32 = 2 * 24 * 60 * 60;
+
+/// How long a customer has to answer after being contacted about a failed delivery, in seconds.
+pub const CUSTOMER_RESPONSE_TIMEOUT: u32 = 7 * 24 * 60 * 60;
+
+/// How long the customer's answer may take to be carried out, in seconds.
+pub const RESOLUTION_TIMEOUT: u32 = 2 * 24 * 60 * 60;
+
+/// Where a saga is, and when it escalates if it gets no further.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum RecoveryStep {
+    /// Waiting until `deadline` for `ContactCustomer` to be carried out.
+    Contacting { deadline: u32 },
+    /// The customer has been contacted and has until `deadline` to answer.
+    AwaitingResponse { deadline: u32 },
+    /// The customer has answered, and the reshipment or refund has until `deadline` to be recorded.
+    Resolving { deadline: u32 },
+}
+
+impl RecoveryStep {
+    const fn deadline(self) -> u32 {
+        match self {
+            Self::Contacting { deadline } | Self::AwaitingResponse { deadline } | Self::Resolving { deadline } => deadline,
+        }
+    }
+
+    const fn overdue(self) -> &'static str {
+        match self {
+            Self::Contacting { .. } => "the customer was not contacted",
+            Self::AwaitingResponse { .. } => "no answer from the customer",
+            Self::Resolving { .. } => "the customer's answer was not carried out",
+        }
+    }
+}
+
+/// A failed delivery that is being resolved with the customer.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DeliveryRecovery {
+    pub order_id: OrderId,
+    pub step: RecoveryStep,
+    pub started_at: u32,
+}
+
+/// Where running sagas are kept between restarts.
+pub trait SagaStore {
+    fn load(&self) -> Result<Vec<DeliveryRecovery>, FileError>;
+    fn save(&mut self, sagas: &[DeliveryRecovery]) -> Result<(), FileError>;
+}
+
+/// Keeps running sagas as a JSON file. A missing file means none are running.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FileSagaStore {
+    file: JsonFile,
+}
+
+impl FileSagaStore {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self { file: JsonFile::new(path) }
+    }
+}
+
+impl SagaStore for FileSagaStore {
+    fn load(&self) -> Result<Vec<DeliveryRecovery>, FileError> {
+        self.file.load()
+    }
+
+    fn save(&mut self, sagas: &[DeliveryRecovery]) -> Result<(), FileError> {
+        self.file.save(sagas)
+    }
+}
+
+/// Resolves failed deliveries that call for `ContactCustomer`. Once the customer has been contacted it waits
+/// for `CustomerResponded` and re-ships as asked, or leaves a refund to `Action::Refund`. A step that is not
+/// done by its deadline escalates. Like `infra::timer::Scheduler`, it is fed events after they are stored, and
+/// a saga only ends once the event that ends it has been observed.
+#[derive(Debug)]
+pub struct DeliveryRecoverySaga<S, C> {
+    sagas: Vec<DeliveryRecovery>,
+    store: S,
+    clock: C,
+}
+
+impl<S: SagaStore, C: Clock> DeliveryRecoverySaga<S, C> {
+    pub fn new(store: S, clock: C) -> Result<Self, FileError> {
+        Ok(Self { sagas: store.load()?, store, clock })
+    }
+
+    pub fn sagas(&self) -> &[DeliveryRecovery] {
+        &self.sagas
+    }
+
+    pub const fn clock_mut(&mut self) -> &mut C {
+        &mut self.clock
+    }
+
+    /// Moves the saga of the event's order along, and returns the events that resolve it if the customer has
+    /// answered. `load` rebuilds an order from its stream, which must already hold `event`. A resolution
+    /// recorded by anyone else ends the saga. Every change is saved before it returns.
+    pub fn observe(&mut self, event: &OrderEvent, load: impl Fn(&str) -> Order) -> Result<Vec<OrderEvent>, FileError> {
+        match event {
+            OrderEvent::OrderDeliveryFailed { order_id, reason, time }
+            | OrderEvent::ShipmentDeliveryFailed { order_id, reason, time, .. }
+                if reason.reason_code.action() == Action::ContactCustomer =>
+            {
+                self.sagas.retain(|saga| &saga.order_id != order_id);
+                self.sagas.push(DeliveryRecovery {
+                    order_id: order_id.clone(),
+                    step: RecoveryStep::Contacting { deadline: time + CUSTOMER_CONTACT_TIMEOUT },
+                    started_at: *time,
+                });
+                self.store.save(&self.sagas)?;
+            }
+            OrderEvent::ActionCompleted { order_id, action: Action::ContactCustomer, time } => {
+                self.advance(order_id, RecoveryStep::AwaitingResponse { deadline: time + CUSTOMER_RESPONSE_TIMEOUT })?;
+            }
+            OrderEvent::CustomerResponded { order_id, resolution, time } => {
+                if self.advance(order_id, RecoveryStep::Resolving { deadline: time + RESOLUTION_TIMEOUT })? {
+                    return Ok(resolve(&load(order_id), resolution, *time).into_iter().collect());
+                }
+            }
+            OrderEvent::OrderReshipped { order_id, .. }
+            | OrderEvent::OrderRefunded { order_id, .. }
+            | OrderEvent::DeliveryEscalated { order_id, .. }
+                if self.sagas.iter().any(|saga| &saga.order_id == order_id) =>
+            {
+                self.sagas.retain(|saga| &saga.order_id != order_id);
+                self.store.save(&self.sagas)?;
+            }
+            _ => {}
+        }
+        Ok(Vec::new())
+    }
+
+    /// Escalates every saga past the deadline of its step. A saga is only dropped once `observe` sees its
+    /// `DeliveryEscalated`, so an escalation that is lost before it is stored is raised again by the next `fire`.
+    pub fn fire(&self) -> Vec<OrderEvent> {
+        let now = self.clock.now();
+        self.sagas
+            .iter()
+            .filter(|saga| saga.step.deadline() <= now)
+            .map(|saga| OrderEvent::DeliveryEscalated {
+                order_id: saga.order_id.clone(),
+                reason: saga.step.overdue().to_string(),
+                time: now,
+            })
+            .collect()
+    }
+
+    /// Moves the order's saga on to `step`. Returns whether there was one.
+    fn advance(&mut self, order_id: &str, step: RecoveryStep) -> Result<bool, FileError> {
+        let Some(saga) = self.sagas.iter_mut().find(|saga| saga.order_id == order_id) else {
+            return Ok(false);
+        };
+        saga.step = step;
+        self.store.save(&self.sagas)?;
+        Ok(true)
+    }
+}
+
+/// The event that carries out the customer's answer. A refund has no event of its own here: the answer raised
+/// `Action::Refund` on the order, and the payment handler records `OrderRefunded` once the money is back. An
+/// order that has moved on meanwhile is escalated instead.
+fn resolve(order: &Order, resolution: &DeliveryResolution, time: u32) -> Option<OrderEvent> {
+    if order.status != State::DeliveryFailed {
+        return Some(OrderEvent::DeliveryEscalated {
+            order_id: order.id.clone(),
+            reason: format!("customer answered while the order was {:?}", order.status),
+            time,
+        });
+    }
+    match resolution {
+        DeliveryResolution::Reship(address) => {
+            Some(OrderEvent::OrderReshipped { order_id: order.id.clone(), address: address.clone(), time })
+        }
+        DeliveryResolution::Refund => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+    use crate::{
+        entities::{Address, CountryCode, PaymentType, Pii, ReasonCode},
+        infra::{
+            payment::{refund_payment, MockGateway},
+            store::OrderStore,
+            test_support::{delivery_failed, item_added, load, payed, sent},
+            timer::ManualClock,
+        },
+    };
+
+    fn append(
+        store: &mut OrderStore, saga: &mut DeliveryRecoverySaga<FileSagaStore, ManualClock>, order_id: &str, event: &OrderEvent,
+    ) -> Vec<OrderEvent> {
+        store.append(order_id, event.clone()).expect("append failed");
+        saga.observe(event, |order_id| load(store, order_id)).expect("saga failed")
+    }
+
+    fn failed(
+        store: &mut OrderStore, saga: &mut DeliveryRecoverySaga<FileSagaStore, ManualClock>, order_id: &str, reason_code: ReasonCode,
+    ) {
+        for event in [
+            item_added(order_id, 1),
+            payed(order_id, PaymentType::Visa, 345, 2),
+            sent(order_id, 3),
+            delivery_failed(order_id, reason_code, 4),
+        ] {
+            append(store, saga, order_id, &event);
+        }
+    }
+
+    fn contacted(
+        store: &mut OrderStore, saga: &mut DeliveryRecoverySaga<FileSagaStore, ManualClock>, order_id: &str, reason_code: ReasonCode,
+    ) {
+        failed(store, saga, order_id, reason_code);
+        let completed = OrderEvent::ActionCompleted { order_id: order_id.to_string(), action: Action::ContactCustomer, time: 10 };
+        append(store, saga, order_id, &completed);
+    }
+
+    fn escalated(order_id: &str, reason: &str, time: u32) -> OrderEvent {
+        OrderEvent::DeliveryEscalated { order_id: order_id.to_string(), reason: reason.to_string(), time }
+    }
+
+    #[test]
+    fn resolves_failed_deliveries_across_restart() {
+        let path = std::env::temp_dir().join(format!("delivery_sagas_{}.json", std::process::id()));
+        let _ = fs::remove_file(&path);
+        let mut store = OrderStore::new();
+        let mut saga = DeliveryRecoverySaga::new(FileSagaStore::new(&path), ManualClock { now: 0 }).expect("loading sagas failed");
+        contacted(&mut store, &mut saga, "1001", ReasonCode::WrongAddress);
+        contacted(&mut store, &mut saga, "1002", ReasonCode::Refused);
+        contacted(&mut store, &mut saga, "1003", ReasonCode::AddressUnreachable);
+        contacted(&mut store, &mut saga, "1004", ReasonCode::RecipientAbsent);
+        failed(&mut store, &mut saga, "1005", ReasonCode::PackageLost);
+        assert_eq!(saga.sagas().len(), 4);
+        assert!(saga.sagas()[..3]
+            .iter()
+            .all(|saga| saga.step == RecoveryStep::AwaitingResponse { deadline: 10 + CUSTOMER_RESPONSE_TIMEOUT }));
+        drop(saga);
+
+        // The customer of 1005 was never contacted.
+        let mut saga = DeliveryRecoverySaga::new(FileSagaStore::new(&path), ManualClock { now: CUSTOMER_RESPONSE_TIMEOUT })
+            .expect("loading sagas failed");
+        let contact_missed = escalated("1005", "the customer was not contacted", CUSTOMER_RESPONSE_TIMEOUT);
+        assert_eq!(saga.fire(), vec![contact_missed.clone()]);
+        append(&mut store, &mut saga, "1005", &contact_missed);
+        assert_eq!(saga.fire(), vec![]);
+
+        let corrected = Address { street: "Karisevej".to_string(), house_number: 43, zip: 4690, country: CountryCode::Dk };
+        let answer = |order_id: &str, resolution| OrderEvent::CustomerResponded { order_id: order_id.to_string(), resolution, time: 20 };
+        let reshipped = append(&mut store, &mut saga, "1001", &answer("1001", DeliveryResolution::Reship(Pii::Plain(corrected.clone()))));
+        assert_eq!(
+            reshipped,
+            vec![OrderEvent::OrderReshipped { order_id: "1001".to_string(), address: Pii::Plain(corrected.clone()), time: 20 }]
+        );
+        assert_eq!(saga.sagas()[0].step, RecoveryStep::Resolving { deadline: 20 + RESOLUTION_TIMEOUT });
+        append(&mut store, &mut saga, "1001", &reshipped[0]);
+        let order = load(&store, "1001");
+        assert_eq!((order.status, order.action, order.address), (State::Payed, Action::PrepareOrder, Some(corrected)));
+
+        // A refund is left to the payment handler, which only records it once the money is back.
+        assert_eq!(append(&mut store, &mut saga, "1002", &answer("1002", DeliveryResolution::Refund)), vec![]);
+        assert_eq!(load(&store, "1002").action, Action::Refund);
+        let refunded = refund_payment(&load(&store, "1002"), &mut MockGateway::new(500), 30).expect("refund failed");
+        append(&mut store, &mut saga, "1002", &refunded);
+        assert_eq!(load(&store, "1002").status, State::Refunded);
+
+        // An escalation is raised until it has been stored.
+        saga.clock_mut().now = 10 + CUSTOMER_RESPONSE_TIMEOUT;
+        let no_answer = escalated("1003", "no answer from the customer", 10 + CUSTOMER_RESPONSE_TIMEOUT);
+        assert_eq!(saga.fire(), vec![no_answer.clone()]);
+        assert_eq!(saga.fire(), vec![no_answer.clone()]);
+        append(&mut store, &mut saga, "1003", &no_answer);
+        let order = load(&store, "1003");
+        assert_eq!((order.status, order.action), (State::DeliveryFailed, Action::CheckOrder));
+
+        assert!(saga.sagas().is_empty());
+        assert_eq!(FileSagaStore::new(&path).load(), Ok(vec![]));
+        let _ = fs::remove_file(&path);
+    }
+}
@@ -0,0 +1,133 @@
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use serde_json::{json, Value};
+
+/// The schema version new events are written with. Bump it together with a new entry in `UPCASTERS`.
+pub const CURRENT_SCHEMA_VERSION: u32 = 4;
+
+/// Upcasters by the version they read: `UPCASTERS[0]` turns a v1 payload into v2, and so on.
+///
+/// - v2: `PaymentAuthorized` gained `expires_at`.
+/// - v3: the personal data of `CustomerAdded` is wrapped in `Pii`, so it can be sealed.
+/// - v4: the addresses of every other order event, and the personal data of customer events, are wrapped in
+///   `Pii` too.
+const UPCASTERS: [fn(Value) -> Value; 3] = [v1_to_v2, v2_to_v3, v3_to_v4];
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SchemaError {
+    Malformed(String),
+    UnsupportedVersion(u32),
+}
+
+/// An event as it is persisted: the serialized payload and the schema version it was written with.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct StoredEvent {
+    pub schema_version: u32,
+    pub payload: Value,
+}
+
+pub fn encode<E: Serialize>(event: &E) -> Result<StoredEvent, SchemaError> {
+    let payload = serde_json::to_value(event).map_err(|err| SchemaError::Malformed(err.to_string()))?;
+    Ok(StoredEvent { schema_version: CURRENT_SCHEMA_VERSION, payload })
+}
+
+/// Brings a stored event up to `CURRENT_SCHEMA_VERSION` by applying each upcaster in turn.
+pub fn upcast(stored: StoredEvent) -> Result<StoredEvent, SchemaError> {
+    if stored.schema_version == 0 || stored.schema_version > CURRENT_SCHEMA_VERSION {
+        return Err(SchemaError::UnsupportedVersion(stored.schema_version));
+    }
+    let payload = UPCASTERS[stored.schema_version as usize - 1..]
+        .iter()
+        .fold(stored.payload, |payload, upcaster| upcaster(payload));
+    Ok(StoredEvent { schema_version: CURRENT_SCHEMA_VERSION, payload })
+}
+
+/// Decodes a stored event of any known version into the current `OrderEvent` or `CustomerEvent`. The two share
+/// one version sequence; their variant names never overlap, so each upcaster only touches the events it knows.
+pub fn decode<E: DeserializeOwned>(stored: StoredEvent) -> Result<E, SchemaError> {
+    serde_json::from_value(upcast(stored)?.payload).map_err(|err| SchemaError::Malformed(err.to_string()))
+}
+
+/// Authorizations written before expiry was tracked get the validity that applied then, from the time they were
+/// made. It is frozen here: changing `payment::AUTHORIZATION_VALIDITY` must not move the expiry of old events.
+fn v1_to_v2(mut payload: Value) -> Value {
+    const V1_AUTHORIZATION_VALIDITY: u64 = 7 * 24 * 60 * 60;
+    if let Some(body) = payload.get_mut("PaymentAuthorized").and_then(Value::as_object_mut) {
+        let time = body.get("time").and_then(Value::as_u64).unwrap_or_default();
+        body.entry("expires_at").or_insert_with(|| json!(time + V1_AUTHORIZATION_VALIDITY));
+    }
+    payload
+}
+
+fn v2_to_v3(mut payload: Value) -> Value {
+    if let Some(body) = payload.get_mut("CustomerAdded").and_then(Value::as_object_mut) {
+        for field in ["first_name", "last_name", "address"] {
+            if let Some(value) = body.get_mut(field) {
+                *value = json!({ "Plain": value.take() });
+            }
+        }
+    }
+    payload
+}
+
+fn v3_to_v4(mut payload: Value) -> Value {
+    let fields: &[(&str, &str)] = &[
+        ("OrderDetailsAdded", "/delivery_address"),
+        ("AddressesResolved", "/shipping_address"),
+        ("AddressesResolved", "/billing_address"),
+        ("CustomerRegistered", "/first_name"),
+        ("CustomerRegistered", "/last_name"),
+        ("CustomerRegistered", "/email"),
+        ("CustomerRegistered", "/address"),
+        ("CustomerAddressChanged", "/address"),
+        ("CustomerEmailChanged", "/email"),
+    ];
+    for (event, field) in fields {
+        if let Some(value) = payload.get_mut(event).and_then(|body| body.pointer_mut(field)).filter(|value| !value.is_null()) {
+            *value = json!({ "Plain": value.take() });
+        }
+    }
+    payload
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        entities::{Order, OrderEvent, OrderEventDiscriminants, State},
+        logic::{aggregate_order, TRANSITIONS},
+    };
+    use fsm::StateMachine;
+    use rstest::rstest;
+    use strum::IntoEnumIterator;
+
+    fn load(fixture: &str) -> Vec<OrderEvent> {
+        let stored: Vec<StoredEvent> = serde_json::from_str(fixture).expect("malformed fixture");
+        stored.into_iter().map(|event| decode(event).expect("decoding failed")).collect()
+    }
+
+    #[rstest]
+    #[case(include_str!("../../fixtures/events/v1.json"))]
+    #[case(include_str!("../../fixtures/events/v2.json"))]
+    #[case(include_str!("../../fixtures/events/v3.json"))]
+    fn old_versions_replay_like_current(#[case] fixture: &str) {
+        let current = load(include_str!("../../fixtures/events/v4.json"));
+        assert_eq!(load(fixture), current);
+
+        let mut machine = StateMachine::new(State::iter().collect(), OrderEventDiscriminants::iter().collect(), TRANSITIONS.to_owned());
+        let order = aggregate_order(load(fixture), Order::new("1234".to_string()), &mut machine);
+        assert_eq!(order.status, State::Delivered);
+        assert_eq!(order.amount, 345);
+    }
+
+    #[test]
+    fn encode_round_trips_and_rejects_unknown_versions() {
+        let event = OrderEvent::OrderSent { order_id: "1234".to_string(), time: 8 };
+        let stored = encode(&event).expect("encoding failed");
+        assert_eq!(stored.schema_version, CURRENT_SCHEMA_VERSION);
+        assert_eq!(decode(stored.clone()), Ok(event));
+        assert_eq!(
+            decode::<OrderEvent>(StoredEvent { schema_version: CURRENT_SCHEMA_VERSION + 1, ..stored }),
+            Err(SchemaError::UnsupportedVersion(CURRENT_SCHEMA_VERSION + 1))
+        );
+    }
+}
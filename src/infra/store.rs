@@ -0,0 +1,183 @@
+use std::collections::HashMap;
+
+use serde::{de::DeserializeOwned, Serialize};
+
+use crate::{
+    entities::{CustomerEvent, OrderEvent},
+    infra::{
+        outbox::OutboxEntry,
+        schema::{decode, encode, SchemaError, StoredEvent},
+        subscription::EventEnvelope,
+    },
+};
+
+/// An in-memory, append-only event store keyed by stream id. Orders and customers are kept in separate
+/// stores, so a customer's data lives in exactly one stream no matter how many orders refer to it. Streams hold
+/// events as they are persisted, tagged with their schema version, and are upcast when they are loaded.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EventStore<E> {
+    streams: HashMap<String, Vec<StoredEvent>>,
+    /// Versions assigned to idempotency keys, per stream.
+    keys: HashMap<String, HashMap<String, usize>>,
+    /// Every appended event, written in the same step as the stream so nothing is stored but not published.
+    outbox: Vec<OutboxEntry<E>>,
+}
+
+pub type OrderStore = EventStore<OrderEvent>;
+pub type CustomerStore = EventStore<CustomerEvent>;
+
+impl<E: Clone + Ord> EventStore<E> {
+    pub fn new() -> Self {
+        Self { streams: HashMap::new(), keys: HashMap::new(), outbox: Vec::new() }
+    }
+
+    /// The ids of all streams, sorted.
+    pub fn streams(&self) -> Vec<String> {
+        let mut streams: Vec<String> = self.streams.keys().cloned().collect();
+        streams.sort();
+        streams
+    }
+
+    /// Outbox entries not yet published, oldest first.
+    pub fn pending(&self) -> Vec<&OutboxEntry<E>> {
+        self.outbox.iter().filter(|entry| !entry.delivered).collect()
+    }
+
+    /// Reads the global log, all streams in append order, after `position`. The outbox doubles as the log: an
+    /// entry's id is its global position.
+    pub fn read_all(&self, position: usize) -> Vec<EventEnvelope<E>> {
+        self.outbox.iter().skip(position).cloned().map(EventEnvelope::from).collect()
+    }
+
+    /// The global position of the last appended event.
+    pub const fn head(&self) -> usize {
+        self.outbox.len()
+    }
+
+    pub fn mark_delivered(&mut self, id: usize) {
+        if let Some(entry) = self.outbox.iter_mut().find(|entry| entry.id == id) {
+            entry.delivered = true;
+        }
+    }
+}
+
+impl<E: Clone + Ord + Serialize + DeserializeOwned> EventStore<E> {
+    /// Appends an event to a stream and returns the stream's new version (its number of events).
+    pub fn append(&mut self, stream: &str, event: E) -> Result<usize, SchemaError> {
+        let stored = encode(&event)?;
+        let events = self.streams.entry(stream.to_string()).or_default();
+        events.push(stored);
+        let version = events.len();
+        self.outbox
+            .push(OutboxEntry { id: self.outbox.len() + 1, stream: stream.to_string(), version, event, delivered: false });
+        Ok(version)
+    }
+
+    /// Appends an event unless the stream already has one with the same idempotency key, so a retried request
+    /// can be appended again safely. Returns the version the key was first assigned.
+    pub fn append_once(&mut self, stream: &str, key: &str, event: E) -> Result<usize, SchemaError> {
+        if let Some(version) = self.keys.get(stream).and_then(|keys| keys.get(key)) {
+            return Ok(*version);
+        }
+        let version = self.append(stream, event)?;
+        self.keys.entry(stream.to_string()).or_default().insert(key.to_string(), version);
+        Ok(version)
+    }
+
+    /// Loads a stream ordered by event time, ready for aggregation. Events written with an older schema are
+    /// upcast to the current one.
+    pub fn load(&self, stream: &str) -> Result<Vec<E>, SchemaError> {
+        let mut events = self.streams.get(stream).into_iter().flatten().cloned().map(decode).collect::<Result<Vec<E>, _>>()?;
+        events.sort_by(std::cmp::Ord::cmp);
+        Ok(events)
+    }
+}
+
+impl<E: Clone + Ord> Default for EventStore<E> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        entities::{Address, CountryCode, Customer, Pii},
+        logic::aggregate_customer,
+    };
+
+    #[test]
+    fn customer_stream_is_separate_from_orders() {
+        let mut customers = CustomerStore::new();
+        let mut orders = OrderStore::new();
+        customers
+            .append(
+                "765432",
+                CustomerEvent::CustomerRegistered {
+                    customer: "765432".to_string(),
+                    first_name: Pii::Plain("Steen".to_string()),
+                    last_name: Pii::Plain("Larsen".to_string()),
+                    email: Pii::Plain("steen@example.dk".to_string()),
+                    address: Pii::Plain(Address { street: "Taagevej".to_string(), house_number: 43, zip: 4600, country: CountryCode::Dk }),
+                    time: 1,
+                },
+            )
+            .expect("append failed");
+        assert_eq!(
+            customers
+                .append(
+                    "765432",
+                    CustomerEvent::CustomerEmailChanged {
+                        customer: "765432".to_string(),
+                        email: Pii::Plain("s@example.dk".to_string()),
+                        time: 2
+                    }
+                )
+                .expect("append failed"),
+            2
+        );
+        orders
+            .append("1234", OrderEvent::ItemAdded { id: "1234".to_string(), order_id: "1234".to_string(), time: 1 })
+            .expect("append failed");
+
+        let customer = aggregate_customer(customers.load("765432").expect("loading failed"), Customer::new("765432".to_string()));
+        assert_eq!(customer.email, "s@example.dk");
+        assert!(orders.load("765432").expect("loading failed").is_empty());
+        assert!(customers.load("1234").expect("loading failed").is_empty());
+    }
+
+    #[test]
+    fn retried_append_is_ignored() {
+        let mut orders = OrderStore::new();
+        let item = OrderEvent::ItemAdded { id: "1234".to_string(), order_id: "1234".to_string(), time: 1 };
+        assert_eq!(orders.append_once("1234", "req-1", item.clone()).expect("append failed"), 1);
+        assert_eq!(orders.append_once("1234", "req-2", item.clone()).expect("append failed"), 2);
+        assert_eq!(orders.append_once("1234", "req-1", item.clone()).expect("append failed"), 1);
+        assert_eq!(orders.load("1234").expect("loading failed").len(), 2);
+
+        assert_eq!(orders.append_once("2345", "req-1", item).expect("append failed"), 1);
+    }
+
+    #[test]
+    fn old_events_are_upcast_on_load() {
+        let mut customers = CustomerStore::new();
+        customers.streams.insert(
+            "765432".to_string(),
+            vec![StoredEvent {
+                schema_version: 3,
+                payload: serde_json::json!({ "CustomerEmailChanged": { "customer": "765432", "email": "s@example.dk", "time": 2 } }),
+            }],
+        );
+        let email = Pii::Plain("s@example.dk".to_string());
+        assert_eq!(
+            customers.load("765432"),
+            Ok(vec![CustomerEvent::CustomerEmailChanged { customer: "765432".to_string(), email, time: 2 }])
+        );
+
+        customers
+            .streams
+            .insert("1".to_string(), vec![StoredEvent { schema_version: 9, payload: serde_json::Value::Null }]);
+        assert_eq!(customers.load("1"), Err(SchemaError::UnsupportedVersion(9)));
+    }
+}
@@ -0,0 +1,126 @@
+use std::collections::HashMap;
+
+use strum::IntoEnumIterator;
+
+use crate::{
+    entities::{Action, OrderId, State},
+    infra::order_list::{OrderList, OrderQuery, OrderSort},
+};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StuckOrder {
+    pub id: OrderId,
+    pub status: State,
+    pub action: Action,
+    pub idle_for: u32,
+}
+
+/// Something a person has to look at. Raised for stuck orders as `Action::CheckOrder`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WorkItem {
+    pub order_id: OrderId,
+    pub action: Action,
+    pub reason: String,
+    pub raised_at: u32,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct StuckReport {
+    pub orders: Vec<StuckOrder>,
+    pub work_items: Vec<WorkItem>,
+}
+
+/// Flags orders whose last event is older than the threshold, in seconds, for their state. States without a
+/// threshold, like the terminal `Delivered` and `Failed`, are never stuck.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StuckOrderDetector {
+    thresholds: HashMap<State, u32>,
+    raise_work_items: bool,
+}
+
+impl StuckOrderDetector {
+    pub const fn new(thresholds: HashMap<State, u32>) -> Self {
+        Self { thresholds, raise_work_items: false }
+    }
+
+    /// Also raise a `CheckOrder` work item for each stuck order that is not already waiting for a check.
+    pub fn with_work_items(self) -> Self {
+        Self { raise_work_items: true, ..self }
+    }
+
+    pub fn detect(&self, orders: &OrderList, now: u32) -> StuckReport {
+        let mut report = StuckReport::default();
+        for (state, threshold) in State::iter().filter_map(|state| Some((state, self.thresholds.get(&state)?))) {
+            let query = OrderQuery { status: Some(state), sort: OrderSort::UpdatedAt, limit: usize::MAX, ..OrderQuery::default() };
+            let Ok(page) = orders.query(&query) else {
+                continue;
+            };
+            // Oldest first, so the first order that is not stuck ends the scan.
+            for summary in page.orders {
+                let idle_for = now.saturating_sub(summary.updated_at);
+                if idle_for <= *threshold {
+                    break;
+                }
+                if self.raise_work_items && summary.action != Action::CheckOrder {
+                    report.work_items.push(WorkItem {
+                        order_id: summary.id.clone(),
+                        action: Action::CheckOrder,
+                        reason: format!("{:?} for {idle_for}s, over the {threshold}s threshold", summary.status),
+                        raised_at: now,
+                    });
+                }
+                report
+                    .orders
+                    .push(StuckOrder { id: summary.id, status: summary.status, action: summary.action, idle_for });
+            }
+        }
+        report
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::mpsc::channel;
+
+    use super::*;
+    use crate::{
+        entities::DeliveryType,
+        infra::{
+            projection::Projector,
+            store::OrderStore,
+            test_support::{append_all, delivered, item_added, pay, sent},
+        },
+    };
+
+    #[test]
+    fn flags_orders_idle_past_their_state_threshold() {
+        let mut store = OrderStore::new();
+        pay(&mut store, "1001", DeliveryType::Gls, 100);
+        pay(&mut store, "1002", DeliveryType::Gls, 5_000);
+        pay(&mut store, "1003", DeliveryType::Gls, 100);
+        append_all(&mut store, "1003", [sent("1003", 200)]);
+        pay(&mut store, "1004", DeliveryType::Gls, 100);
+        append_all(&mut store, "1004", [sent("1004", 200), delivered("1004", 300)]);
+        // Never paid.
+        append_all(&mut store, "1005", [item_added("1005", 100)]);
+        let mut orders = OrderList::new();
+        Projector::new("order_list", channel().1).run(&mut orders, &store).expect("run failed");
+
+        let detector = StuckOrderDetector::new(HashMap::from([(State::InProgress, 86_400), (State::Payed, 3_600), (State::Sent, 10_000)]));
+        let report = detector.detect(&orders, 6_000);
+        assert_eq!(
+            report.orders,
+            vec![StuckOrder { id: "1001".to_string(), status: State::Payed, action: Action::PrepareOrder, idle_for: 5_900 }]
+        );
+        assert!(report.work_items.is_empty());
+
+        let report = detector.clone().with_work_items().detect(&orders, 20_000);
+        let stuck: Vec<_> = report.orders.iter().map(|order| (order.id.as_str(), order.status)).collect();
+        assert_eq!(stuck, vec![("1001", State::Payed), ("1002", State::Payed), ("1003", State::Sent)]);
+        assert_eq!(report.work_items.len(), 3);
+        assert!(report.work_items.iter().all(|item| item.action == Action::CheckOrder));
+
+        let stuck: Vec<_> = detector.detect(&orders, 90_000).orders.into_iter().map(|order| (order.id, order.status)).collect();
+        assert_eq!(stuck[0], ("1005".to_string(), State::InProgress));
+    }
+}
@@ -0,0 +1,320 @@
+use std::{
+    collections::HashMap,
+    path::PathBuf,
+    sync::mpsc::{Receiver, TryRecvError},
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::infra::{
+    json_file::{FileError, JsonFile},
+    outbox::OutboxEntry,
+    store::EventStore,
+};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SubscriptionError {
+    Handler {
+        subscriber: String,
+        position: usize,
+        reason: String,
+    },
+    Checkpoint {
+        subscriber: String,
+        error: FileError,
+    },
+}
+
+/// An event with its place in the global log and in its own stream.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EventEnvelope<E> {
+    pub position: usize,
+    pub stream: String,
+    pub version: usize,
+    pub event: E,
+}
+
+impl<E> From<OutboxEntry<E>> for EventEnvelope<E> {
+    fn from(entry: OutboxEntry<E>) -> Self {
+        Self { position: entry.id, stream: entry.stream, version: entry.version, event: entry.event }
+    }
+}
+
+/// Where subscribers keep the global position of the last event they handled.
+pub trait CheckpointStore {
+    fn load(&self, subscriber: &str) -> usize;
+    fn save(&mut self, subscriber: &str, position: usize) -> Result<(), FileError>;
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct LocalCheckpoints {
+    positions: HashMap<String, usize>,
+}
+
+impl LocalCheckpoints {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl CheckpointStore for LocalCheckpoints {
+    fn load(&self, subscriber: &str) -> usize {
+        self.positions.get(subscriber).copied().unwrap_or_default()
+    }
+
+    fn save(&mut self, subscriber: &str, position: usize) -> Result<(), FileError> {
+        self.positions.insert(subscriber.to_string(), position);
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+struct Checkpoint {
+    subscriber: String,
+    position: usize,
+}
+
+/// Keeps checkpoints as a JSON file, so a restarted subscriber resumes where it stopped. They are read once, when
+/// the file is opened, and the file is rewritten on every save.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FileCheckpoints {
+    file: JsonFile,
+    positions: HashMap<String, usize>,
+}
+
+impl FileCheckpoints {
+    pub fn open(path: impl Into<PathBuf>) -> Result<Self, FileError> {
+        let file = JsonFile::new(path);
+        let positions = file
+            .load()?
+            .into_iter()
+            .map(|checkpoint: Checkpoint| (checkpoint.subscriber, checkpoint.position))
+            .collect();
+        Ok(Self { file, positions })
+    }
+}
+
+impl CheckpointStore for FileCheckpoints {
+    fn load(&self, subscriber: &str) -> usize {
+        self.positions.get(subscriber).copied().unwrap_or_default()
+    }
+
+    /// Only takes the new position once it is on disk, so a failed save leaves the old one in place.
+    fn save(&mut self, subscriber: &str, position: usize) -> Result<(), FileError> {
+        let mut checkpoints: Vec<_> = self
+            .positions
+            .iter()
+            .filter(|(name, _)| *name != subscriber)
+            .map(|(name, position)| Checkpoint { subscriber: name.clone(), position: *position })
+            .chain([Checkpoint { subscriber: subscriber.to_string(), position }])
+            .collect();
+        checkpoints.sort_by(|left, right| left.subscriber.cmp(&right.subscriber));
+        self.file.save(&checkpoints)?;
+        self.positions.insert(subscriber.to_string(), position);
+        Ok(())
+    }
+}
+
+/// A subscriber's view of the global log. It starts at its checkpoint and catches up from the store, then goes
+/// live on the entries the outbox relay publishes to `live`.
+///
+/// Delivery is at least once: the checkpoint only moves after the handler succeeds, and a failed event is
+/// delivered again on the next poll, so handlers must tolerate seeing an event twice. So must they after a
+/// checkpoint that failed to save, once the subscriber restarts.
+#[derive(Debug)]
+pub struct Subscription<E> {
+    subscriber: String,
+    position: usize,
+    live: Receiver<OutboxEntry<E>>,
+    caught_up: bool,
+}
+
+impl<E: Clone + Ord> Subscription<E> {
+    pub fn new(subscriber: &str, checkpoints: &impl CheckpointStore, live: Receiver<OutboxEntry<E>>) -> Self {
+        Self { subscriber: subscriber.to_string(), position: checkpoints.load(subscriber), live, caught_up: false }
+    }
+
+    pub const fn position(&self) -> usize {
+        self.position
+    }
+
+    pub const fn is_live(&self) -> bool {
+        self.caught_up
+    }
+
+    /// Starts over from the beginning of the log, for a subscriber that is rebuilding its view.
+    pub fn rewind(&mut self, checkpoints: &mut impl CheckpointStore) -> Result<(), SubscriptionError> {
+        self.position = 0;
+        self.caught_up = false;
+        self.save(checkpoints)
+    }
+
+    /// Delivers everything available to `handler` and returns how many events it handled.
+    pub fn poll(
+        &mut self, store: &EventStore<E>, checkpoints: &mut impl CheckpointStore,
+        mut handler: impl FnMut(&EventEnvelope<E>) -> Result<(), String>,
+    ) -> Result<usize, SubscriptionError> {
+        let mut count = 0;
+        if !self.caught_up {
+            count += self.catch_up(store, checkpoints, &mut handler)?;
+        }
+        loop {
+            let envelope = match self.live.try_recv() {
+                Ok(entry) => EventEnvelope::from(entry),
+                Err(TryRecvError::Empty | TryRecvError::Disconnected) => return Ok(count),
+            };
+            if envelope.position <= self.position {
+                // Already handled while catching up.
+                continue;
+            }
+            if envelope.position > self.position + 1 {
+                // Missed live entries; the store has them all.
+                count += self.catch_up(store, checkpoints, &mut handler)?;
+                continue;
+            }
+            count += self.deliver(&envelope, checkpoints, &mut handler)?;
+        }
+    }
+
+    fn catch_up(
+        &mut self, store: &EventStore<E>, checkpoints: &mut impl CheckpointStore,
+        handler: &mut impl FnMut(&EventEnvelope<E>) -> Result<(), String>,
+    ) -> Result<usize, SubscriptionError> {
+        let mut count = 0;
+        for envelope in store.read_all(self.position) {
+            count += self.deliver(&envelope, checkpoints, handler)?;
+        }
+        self.caught_up = true;
+        Ok(count)
+    }
+
+    fn deliver(
+        &mut self, envelope: &EventEnvelope<E>, checkpoints: &mut impl CheckpointStore,
+        handler: &mut impl FnMut(&EventEnvelope<E>) -> Result<(), String>,
+    ) -> Result<usize, SubscriptionError> {
+        if let Err(reason) = handler(envelope) {
+            // Whatever was taken off the live channel is lost, so start over from the store.
+            self.caught_up = false;
+            return Err(SubscriptionError::Handler { subscriber: self.subscriber.clone(), position: envelope.position, reason });
+        }
+        self.position = envelope.position;
+        self.save(checkpoints)?;
+        Ok(1)
+    }
+
+    fn save(&self, checkpoints: &mut impl CheckpointStore) -> Result<(), SubscriptionError> {
+        checkpoints
+            .save(&self.subscriber, self.position)
+            .map_err(|error| SubscriptionError::Checkpoint { subscriber: self.subscriber.clone(), error })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        entities::OrderEvent,
+        infra::{
+            outbox::{relay, ChannelPublisher},
+            store::OrderStore,
+        },
+    };
+    use std::{fs, sync::mpsc::channel};
+
+    fn item(order_id: &str, time: u32) -> OrderEvent {
+        OrderEvent::ItemAdded { id: format!("{order_id}-{time}"), order_id: order_id.to_string(), time }
+    }
+
+    #[test]
+    fn catches_up_then_goes_live() {
+        let mut store = OrderStore::new();
+        let mut checkpoints = LocalCheckpoints::new();
+        let (sender, receiver) = channel();
+        let mut publisher = ChannelPublisher::new(sender);
+        store.append("1234", item("1234", 1)).expect("append failed");
+        store.append("2345", item("2345", 2)).expect("append failed");
+        relay(&mut store, &mut publisher).expect("relay failed");
+
+        let mut subscription = Subscription::new("dashboard", &checkpoints, receiver);
+        let mut seen = Vec::new();
+        let handled = subscription.poll(&store, &mut checkpoints, |envelope| {
+            seen.push(envelope.position);
+            Ok(())
+        });
+        assert_eq!(handled, Ok(2));
+        assert!(subscription.is_live());
+
+        store.append("1234", item("1234", 3)).expect("append failed");
+        relay(&mut store, &mut publisher).expect("relay failed");
+        subscription
+            .poll(&store, &mut checkpoints, |envelope| {
+                seen.push(envelope.position);
+                Ok(())
+            })
+            .expect("poll failed");
+        assert_eq!(seen, vec![1, 2, 3]);
+        assert_eq!(checkpoints.load("dashboard"), 3);
+    }
+
+    #[test]
+    fn failed_event_is_redelivered_from_checkpoint() {
+        let mut store = OrderStore::new();
+        let mut checkpoints = LocalCheckpoints::new();
+        let (_sender, receiver) = channel();
+        store.append("1234", item("1234", 1)).expect("append failed");
+        store.append("1234", item("1234", 2)).expect("append failed");
+
+        let mut subscription = Subscription::new("dashboard", &checkpoints, receiver);
+        let failed = subscription.poll(&store, &mut checkpoints, |envelope| match envelope.position {
+            2 => Err("database down".to_string()),
+            _ => Ok(()),
+        });
+        assert_eq!(
+            failed,
+            Err(SubscriptionError::Handler { subscriber: "dashboard".to_string(), position: 2, reason: "database down".to_string() })
+        );
+        assert_eq!(checkpoints.load("dashboard"), 1);
+
+        // A restarted subscriber resumes from its checkpoint.
+        let (_sender, receiver) = channel();
+        let mut restarted = Subscription::new("dashboard", &checkpoints, receiver);
+        assert_eq!(restarted.position(), 1);
+        let mut seen = Vec::new();
+        restarted
+            .poll(&store, &mut checkpoints, |envelope| {
+                seen.push(envelope.position);
+                Ok(())
+            })
+            .expect("poll failed");
+        assert_eq!(seen, vec![2]);
+    }
+
+    #[test]
+    fn file_checkpoints_survive_restart() {
+        let path = std::env::temp_dir().join(format!("checkpoints_{}.json", std::process::id()));
+        let _ = fs::remove_file(&path);
+        let mut store = OrderStore::new();
+        store.append("1234", item("1234", 1)).expect("append failed");
+        store.append("2345", item("2345", 2)).expect("append failed");
+        let mut checkpoints = FileCheckpoints::open(&path).expect("loading checkpoints failed");
+        let (_sender, receiver) = channel();
+        let mut subscription = Subscription::new("dashboard", &checkpoints, receiver);
+        assert_eq!(subscription.poll(&store, &mut checkpoints, |_| Ok(())), Ok(2));
+        drop(checkpoints);
+
+        store.append("1234", item("1234", 3)).expect("append failed");
+        let mut checkpoints = FileCheckpoints::open(&path).expect("loading checkpoints failed");
+        assert_eq!((checkpoints.load("dashboard"), checkpoints.load("billing")), (2, 0));
+        let (_sender, receiver) = channel();
+        let mut restarted = Subscription::new("dashboard", &checkpoints, receiver);
+        let mut seen = Vec::new();
+        restarted
+            .poll(&store, &mut checkpoints, |envelope| {
+                seen.push(envelope.position);
+                Ok(())
+            })
+            .expect("poll failed");
+        assert_eq!(seen, vec![3]);
+        let _ = fs::remove_file(&path);
+    }
+}
@@ -0,0 +1,76 @@
+use fsm::StateMachine;
+use strum::IntoEnumIterator;
+
+use crate::{
+    entities::{DeliveryType, Order, OrderEvent, OrderEventDiscriminants, PaymentType, Reason, ReasonCode, State},
+    infra::store::OrderStore,
+    logic::{aggregate_order, TRANSITIONS},
+};
+
+/// The customer fixture orders belong to, unless a test names another.
+pub const CUSTOMER: &str = "765432";
+
+/// Adds the order's first item, `<order_id>-1`.
+pub fn item_added(order_id: &str, time: u32) -> OrderEvent {
+    OrderEvent::ItemAdded { id: format!("{order_id}-1"), order_id: order_id.to_string(), time }
+}
+
+pub fn details_added(order_id: &str, delivery_type: DeliveryType, customer: &str, time: u32) -> OrderEvent {
+    OrderEvent::OrderDetailsAdded {
+        order_id: order_id.to_string(),
+        delivery_type,
+        delivery_address: None,
+        customer: customer.to_string(),
+        time,
+    }
+}
+
+pub fn payed(order_id: &str, payment_type: PaymentType, amount: u32, time: u32) -> OrderEvent {
+    OrderEvent::OrderPayed { order_id: order_id.to_string(), payment_type, amount, time }
+}
+
+pub fn sent(order_id: &str, time: u32) -> OrderEvent {
+    OrderEvent::OrderSent { order_id: order_id.to_string(), time }
+}
+
+pub fn delivered(order_id: &str, time: u32) -> OrderEvent {
+    OrderEvent::OrderDelivered { order_id: order_id.to_string(), time }
+}
+
+pub fn delivery_failed(order_id: &str, reason_code: ReasonCode, time: u32) -> OrderEvent {
+    OrderEvent::OrderDeliveryFailed {
+        order_id: order_id.to_string(),
+        reason: Reason { reason_code, reason_message: format!("{reason_code:?}") },
+        time,
+    }
+}
+
+pub fn append_all(store: &mut OrderStore, order_id: &str, events: impl IntoIterator<Item = OrderEvent>) {
+    for event in events {
+        store.append(order_id, event).expect("append failed");
+    }
+}
+
+/// Places an order of one item for `CUSTOMER`: the item at `time` and the order details a second later.
+pub fn place(store: &mut OrderStore, order_id: &str, delivery_type: DeliveryType, time: u32) {
+    append_all(
+        store,
+        order_id,
+        [
+            item_added(order_id, time),
+            details_added(order_id, delivery_type, CUSTOMER, time + 1),
+        ],
+    );
+}
+
+/// Places an order two seconds before `time` and pays 100 for it by card at `time`.
+pub fn pay(store: &mut OrderStore, order_id: &str, delivery_type: DeliveryType, time: u32) {
+    place(store, order_id, delivery_type, time - 2);
+    append_all(store, order_id, [payed(order_id, PaymentType::Visa, 100, time)]);
+}
+
+/// Replays an order from the store on a fresh state machine.
+pub fn load(store: &OrderStore, order_id: &str) -> Order {
+    let mut machine = StateMachine::new(State::iter().collect(), OrderEventDiscriminants::iter().collect(), TRANSITIONS.to_owned());
+    aggregate_order(store.load(order_id).expect("loading failed"), Order::new(order_id.to_string()), &mut machine)
+}
@@ -0,0 +1,246 @@
+use std::{
+    path::PathBuf,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    entities::{Order, OrderEvent, OrderId, State},
+    infra::json_file::{FileError, JsonFile},
+};
+
+/// How long an order may go unpaid after its last change before it expires, in seconds.
+pub const UNPAID_ORDER_TTL: u32 = 48 * 60 * 60;
+
+pub trait Clock {
+    /// Seconds since the Unix epoch, like event times.
+    fn now(&self) -> u32;
+}
+
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> u32 {
+        let seconds = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |since| since.as_secs());
+        u32::try_from(seconds).unwrap_or(u32::MAX)
+    }
+}
+
+/// A clock that only moves when told to.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub struct ManualClock {
+    pub now: u32,
+}
+
+impl Clock for ManualClock {
+    fn now(&self) -> u32 {
+        self.now
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum TimerKind {
+    ExpireUnpaid,
+}
+
+/// A domain event due at `due_at`. There is at most one timer of each kind per order.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Timer {
+    pub order_id: OrderId,
+    pub kind: TimerKind,
+    pub due_at: u32,
+}
+
+/// Where timers are kept between restarts.
+pub trait TimerStore {
+    fn load(&self) -> Result<Vec<Timer>, FileError>;
+    fn save(&mut self, timers: &[Timer]) -> Result<(), FileError>;
+}
+
+/// Keeps timers as a JSON file. A missing file means no timers.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FileTimerStore {
+    file: JsonFile,
+}
+
+impl FileTimerStore {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self { file: JsonFile::new(path) }
+    }
+}
+
+impl TimerStore for FileTimerStore {
+    fn load(&self) -> Result<Vec<Timer>, FileError> {
+        self.file.load()
+    }
+
+    fn save(&mut self, timers: &[Timer]) -> Result<(), FileError> {
+        self.file.save(timers)
+    }
+}
+
+/// Schedules future domain events and raises them once they are due. Every change is saved to the store
+/// before it returns, so a restarted scheduler picks up exactly where the last one stopped.
+#[derive(Debug)]
+pub struct Scheduler<S, C> {
+    timers: Vec<Timer>,
+    store: S,
+    clock: C,
+}
+
+impl<S: TimerStore, C: Clock> Scheduler<S, C> {
+    pub fn new(store: S, clock: C) -> Result<Self, FileError> {
+        Ok(Self { timers: store.load()?, store, clock })
+    }
+
+    pub fn timers(&self) -> &[Timer] {
+        &self.timers
+    }
+
+    pub const fn clock_mut(&mut self) -> &mut C {
+        &mut self.clock
+    }
+
+    /// Sets or moves the order's timer of this kind.
+    pub fn schedule(&mut self, order_id: &str, kind: TimerKind, due_at: u32) -> Result<(), FileError> {
+        self.timers.retain(|timer| timer.order_id != order_id || timer.kind != kind);
+        self.timers.push(Timer { order_id: order_id.to_string(), kind, due_at });
+        self.store.save(&self.timers)
+    }
+
+    pub fn cancel(&mut self, order_id: &str, kind: TimerKind) -> Result<(), FileError> {
+        self.timers.retain(|timer| timer.order_id != order_id || timer.kind != kind);
+        self.store.save(&self.timers)
+    }
+
+    /// Keeps timers in step with an order's events: every change to an unpaid order pushes its expiry back,
+    /// and paying cancels it. An expired authorization leaves the order unpaid again, so it restarts the timer.
+    pub fn observe(&mut self, event: &OrderEvent) -> Result<(), FileError> {
+        match event {
+            OrderEvent::ItemAdded { order_id, time, .. }
+            | OrderEvent::ItemDeleted { order_id, time, .. }
+            | OrderEvent::OrderDetailsAdded { order_id, time, .. }
+            | OrderEvent::AddressesResolved { order_id, time, .. }
+            | OrderEvent::PaymentDeclined { order_id, time, .. }
+            | OrderEvent::PaymentAuthorizationExpired { order_id, time, .. } => {
+                self.schedule(order_id, TimerKind::ExpireUnpaid, time + UNPAID_ORDER_TTL)
+            }
+            OrderEvent::OrderPayed { order_id, .. }
+            | OrderEvent::PaymentAuthorized { order_id, .. }
+            | OrderEvent::OrderExpired { order_id, .. } => self.cancel(order_id, TimerKind::ExpireUnpaid),
+            _ => Ok(()),
+        }
+    }
+
+    /// Raises the events of all due timers. A timer is only dropped once `observe` sees its event, so an event
+    /// that is lost before it is stored is raised again by the next `fire`. The timer of an order that has been
+    /// paid meanwhile is dropped without an event. `load` rebuilds an order from its stream.
+    pub fn fire(&mut self, load: impl Fn(&str) -> Order) -> Result<Vec<OrderEvent>, FileError> {
+        let now = self.clock.now();
+        let mut events = Vec::new();
+        let before = self.timers.len();
+        self.timers.retain(|timer| {
+            if timer.due_at > now {
+                return true;
+            }
+            match timer.kind {
+                TimerKind::ExpireUnpaid if matches!(load(&timer.order_id).status, State::Empty | State::InProgress) => {
+                    events.push(OrderEvent::OrderExpired { order_id: timer.order_id.clone(), time: now });
+                    true
+                }
+                TimerKind::ExpireUnpaid => false,
+            }
+        });
+        if self.timers.len() != before {
+            self.store.save(&self.timers)?;
+        }
+        Ok(events)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+    use crate::{
+        entities::PaymentType,
+        infra::{
+            store::OrderStore,
+            test_support::{item_added, load, payed},
+        },
+    };
+
+    fn append(store: &mut OrderStore, scheduler: &mut Scheduler<FileTimerStore, ManualClock>, order_id: &str, event: OrderEvent) {
+        scheduler.observe(&event).expect("scheduling failed");
+        store.append(order_id, event).expect("append failed");
+    }
+
+    #[test]
+    fn unpaid_order_expires_across_restart() {
+        let path = std::env::temp_dir().join(format!("order_timers_{}.json", std::process::id()));
+        let _ = fs::remove_file(&path);
+        let mut store = OrderStore::new();
+        let mut scheduler = Scheduler::new(FileTimerStore::new(&path), ManualClock { now: 0 }).expect("loading timers failed");
+        append(&mut store, &mut scheduler, "1001", item_added("1001", 10));
+        append(&mut store, &mut scheduler, "1002", item_added("1002", 10));
+        append(&mut store, &mut scheduler, "1002", payed("1002", PaymentType::Visa, 100, 20));
+        assert_eq!(
+            scheduler.timers(),
+            [Timer { order_id: "1001".to_string(), kind: TimerKind::ExpireUnpaid, due_at: 10 + UNPAID_ORDER_TTL }]
+        );
+        drop(scheduler);
+
+        let mut scheduler =
+            Scheduler::new(FileTimerStore::new(&path), ManualClock { now: UNPAID_ORDER_TTL }).expect("loading timers failed");
+        assert_eq!(scheduler.fire(|order_id| load(&store, order_id)), Ok(vec![]));
+        scheduler.clock_mut().now = 10 + UNPAID_ORDER_TTL;
+        let expired = scheduler.fire(|order_id| load(&store, order_id)).expect("firing failed");
+        assert_eq!(expired, vec![OrderEvent::OrderExpired { order_id: "1001".to_string(), time: 10 + UNPAID_ORDER_TTL }]);
+        // Not stored yet, so the timer is still there and fires again.
+        assert_eq!(scheduler.fire(|order_id| load(&store, order_id)), Ok(expired.clone()));
+
+        append(&mut store, &mut scheduler, "1001", expired[0].clone());
+        assert_eq!(load(&store, "1001").status, State::Expired);
+        assert!(scheduler.timers().is_empty());
+        assert_eq!(FileTimerStore::new(&path).load(), Ok(vec![]));
+        let _ = fs::remove_file(&path);
+    }
+
+    #[test]
+    fn expired_authorization_restarts_the_timer() {
+        let path = std::env::temp_dir().join(format!("order_timers_authorization_{}.json", std::process::id()));
+        let _ = fs::remove_file(&path);
+        let mut store = OrderStore::new();
+        let mut scheduler = Scheduler::new(FileTimerStore::new(&path), ManualClock { now: 0 }).expect("loading timers failed");
+        append(&mut store, &mut scheduler, "1001", item_added("1001", 10));
+        append(
+            &mut store,
+            &mut scheduler,
+            "1001",
+            OrderEvent::PaymentAuthorized {
+                order_id: "1001".to_string(),
+                authorization_id: "auth-1".to_string(),
+                payment_type: PaymentType::Visa,
+                amount: 100,
+                expires_at: 30,
+                time: 20,
+            },
+        );
+        assert!(scheduler.timers().is_empty());
+        append(
+            &mut store,
+            &mut scheduler,
+            "1001",
+            OrderEvent::PaymentAuthorizationExpired { order_id: "1001".to_string(), authorization_id: "auth-1".to_string(), time: 30 },
+        );
+        assert_eq!(load(&store, "1001").status, State::InProgress);
+        assert_eq!(
+            scheduler.timers(),
+            [Timer { order_id: "1001".to_string(), kind: TimerKind::ExpireUnpaid, due_at: 30 + UNPAID_ORDER_TTL }]
+        );
+        let _ = fs::remove_file(&path);
+    }
+}
@@ -0,0 +1,247 @@
+use serde_json::Value;
+
+use crate::entities::{DeliveryType, Order, OrderEvent, Reason, ReasonCode};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TrackingError {
+    Malformed(String),
+    MissingField(&'static str),
+    UnknownStatusCode { carrier: DeliveryType, code: String },
+    UnknownShipment { order_id: String, tracking_number: String },
+}
+
+/// What a carrier status code means for the order, independent of the carrier's own vocabulary.
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum CarrierStatus {
+    InTransit,
+    Delivered,
+    Failed(ReasonCode),
+}
+
+struct TrackingUpdate {
+    order_id: String,
+    tracking_number: String,
+    code: String,
+    description: String,
+    time: u32,
+}
+
+/// Parses a carrier tracking webhook payload into the order event it implies. In-transit updates carry
+/// no event and return `Ok(None)`; status codes we have no mapping for are an error, never ignored. A
+/// delivery or failure is matched to its shipment by tracking number, among the shipments of the order `load`
+/// rebuilds.
+pub fn parse_tracking(carrier: DeliveryType, payload: &str, load: impl Fn(&str) -> Order) -> Result<Option<OrderEvent>, TrackingError> {
+    let json: Value = serde_json::from_str(payload).map_err(|err| TrackingError::Malformed(err.to_string()))?;
+    let update = match carrier {
+        DeliveryType::Gls => gls_update(&json)?,
+        DeliveryType::Ups => ups_update(&json)?,
+        DeliveryType::Bring => bring_update(&json)?,
+    };
+    let status = match carrier {
+        DeliveryType::Gls => gls_status(&update.code),
+        DeliveryType::Ups => ups_status(&update.code),
+        DeliveryType::Bring => bring_status(&update.code),
+    }
+    .ok_or_else(|| TrackingError::UnknownStatusCode { carrier, code: update.code.clone() })?;
+    let failure = match status {
+        CarrierStatus::InTransit => return Ok(None),
+        CarrierStatus::Delivered => None,
+        CarrierStatus::Failed(reason_code) => Some(Reason { reason_code, reason_message: update.description }),
+    };
+    let order = load(&update.order_id);
+    let shipment_id = order
+        .shipments
+        .iter()
+        .find(|shipment| shipment.tracking_number == update.tracking_number)
+        .map(|shipment| shipment.id.clone())
+        .ok_or_else(|| TrackingError::UnknownShipment {
+            order_id: update.order_id.clone(),
+            tracking_number: update.tracking_number.clone(),
+        })?;
+    Ok(Some(match failure {
+        Some(reason) => OrderEvent::ShipmentDeliveryFailed { order_id: update.order_id, shipment_id, reason, time: update.time },
+        None => OrderEvent::ShipmentDelivered { order_id: update.order_id, shipment_id, time: update.time },
+    }))
+}
+
+fn text(json: &Value, pointer: &'static str) -> Result<String, TrackingError> {
+    json.pointer(pointer)
+        .and_then(Value::as_str)
+        .map(str::to_string)
+        .ok_or(TrackingError::MissingField(pointer))
+}
+
+fn time(json: &Value, pointer: &'static str) -> Result<u32, TrackingError> {
+    json.pointer(pointer)
+        .and_then(Value::as_u64)
+        .and_then(|time| u32::try_from(time).ok())
+        .ok_or(TrackingError::MissingField(pointer))
+}
+
+fn gls_update(json: &Value) -> Result<TrackingUpdate, TrackingError> {
+    Ok(TrackingUpdate {
+        order_id: text(json, "/reference")?,
+        tracking_number: text(json, "/parcelNumber")?,
+        code: text(json, "/event/code")?,
+        description: text(json, "/event/description")?,
+        time: time(json, "/event/timestamp")?,
+    })
+}
+
+/// UPS reports a status type and a code; the code only matters for exceptions (type `X`).
+fn ups_update(json: &Value) -> Result<TrackingUpdate, TrackingError> {
+    let status_type = text(json, "/activity/status/type")?;
+    let code = if status_type == "X" {
+        format!("X:{}", text(json, "/activity/status/code")?)
+    } else {
+        status_type
+    };
+    Ok(TrackingUpdate {
+        order_id: text(json, "/shipmentReference")?,
+        tracking_number: text(json, "/trackingNumber")?,
+        code,
+        description: text(json, "/activity/status/description")?,
+        time: time(json, "/activity/gmtTime")?,
+    })
+}
+
+/// Bring lists package events newest first; deviations carry a separate deviation code.
+fn bring_update(json: &Value) -> Result<TrackingUpdate, TrackingError> {
+    let package = json
+        .pointer("/consignmentSet/0/packageSet/0")
+        .ok_or(TrackingError::MissingField("/consignmentSet/0/packageSet/0"))?;
+    let event = package.pointer("/eventSet/0").ok_or(TrackingError::MissingField("/eventSet/0"))?;
+    let status = text(event, "/status")?;
+    let code = if status == "DEVIATION" {
+        format!("DEVIATION:{}", text(event, "/deviationCode")?)
+    } else {
+        status
+    };
+    Ok(TrackingUpdate {
+        order_id: text(package, "/shipmentReference")?,
+        tracking_number: text(package, "/packageNumber")?,
+        code,
+        description: text(event, "/description")?,
+        time: time(event, "/unixTime")?,
+    })
+}
+
+fn gls_status(code: &str) -> Option<CarrierStatus> {
+    match code {
+        "PREADVICE" | "INWAREHOUSE" | "INTRANSIT" | "INDELIVERY" => Some(CarrierStatus::InTransit),
+        "DELIVERED" | "DELIVEREDPS" => Some(CarrierStatus::Delivered),
+        "LOST" => Some(CarrierStatus::Failed(ReasonCode::PackageLost)),
+        "WRONGADDRESS" => Some(CarrierStatus::Failed(ReasonCode::WrongAddress)),
+        "NOTATHOME" => Some(CarrierStatus::Failed(ReasonCode::RecipientAbsent)),
+        "REFUSED" => Some(CarrierStatus::Failed(ReasonCode::Refused)),
+        "DAMAGED" => Some(CarrierStatus::Failed(ReasonCode::Damaged)),
+        "CUSTOMS" => Some(CarrierStatus::Failed(ReasonCode::CustomsHold)),
+        "NOACCESS" => Some(CarrierStatus::Failed(ReasonCode::AddressUnreachable)),
+        "WEATHER" => Some(CarrierStatus::Failed(ReasonCode::WeatherDelay)),
+        "NOTDELIVERED" => Some(CarrierStatus::Failed(ReasonCode::Other)),
+        _ => None,
+    }
+}
+
+fn ups_status(code: &str) -> Option<CarrierStatus> {
+    match code {
+        "M" | "P" | "I" => Some(CarrierStatus::InTransit),
+        "D" => Some(CarrierStatus::Delivered),
+        "X:LP" => Some(CarrierStatus::Failed(ReasonCode::PackageLost)),
+        "X:UA" => Some(CarrierStatus::Failed(ReasonCode::WrongAddress)),
+        "X:NH" => Some(CarrierStatus::Failed(ReasonCode::RecipientAbsent)),
+        "X:RF" => Some(CarrierStatus::Failed(ReasonCode::Refused)),
+        "X:DM" => Some(CarrierStatus::Failed(ReasonCode::Damaged)),
+        "X:CH" => Some(CarrierStatus::Failed(ReasonCode::CustomsHold)),
+        "X:NA" => Some(CarrierStatus::Failed(ReasonCode::AddressUnreachable)),
+        "X:WD" => Some(CarrierStatus::Failed(ReasonCode::WeatherDelay)),
+        "X:EX" => Some(CarrierStatus::Failed(ReasonCode::Other)),
+        _ => None,
+    }
+}
+
+fn bring_status(code: &str) -> Option<CarrierStatus> {
+    match code {
+        "PRE_NOTIFIED" | "IN_TRANSIT" | "TRANSPORT_TO_RECIPIENT" | "READY_FOR_PICKUP" => Some(CarrierStatus::InTransit),
+        "DELIVERED" | "DELIVERED_SENDER" => Some(CarrierStatus::Delivered),
+        "DEVIATION:LOST" => Some(CarrierStatus::Failed(ReasonCode::PackageLost)),
+        "DEVIATION:ADDRESS_UNKNOWN" => Some(CarrierStatus::Failed(ReasonCode::WrongAddress)),
+        "DEVIATION:RECIPIENT_NOT_HOME" => Some(CarrierStatus::Failed(ReasonCode::RecipientAbsent)),
+        "DEVIATION:REFUSED" => Some(CarrierStatus::Failed(ReasonCode::Refused)),
+        "DEVIATION:DAMAGED" => Some(CarrierStatus::Failed(ReasonCode::Damaged)),
+        "DEVIATION:CUSTOMS" => Some(CarrierStatus::Failed(ReasonCode::CustomsHold)),
+        "DEVIATION:NO_ACCESS" => Some(CarrierStatus::Failed(ReasonCode::AddressUnreachable)),
+        "DEVIATION:WEATHER" => Some(CarrierStatus::Failed(ReasonCode::WeatherDelay)),
+        "DEVIATION:OTHER" => Some(CarrierStatus::Failed(ReasonCode::Other)),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::entities::{Shipment, ShipmentState};
+    use rstest::rstest;
+
+    /// An order with one shipment per carrier, under the tracking numbers the fixtures report.
+    fn load(order_id: &str) -> Order {
+        let mut order = Order::new(order_id.to_string());
+        order.shipments = [
+            (DeliveryType::Gls, "GLS00000000001"),
+            (DeliveryType::Ups, "1Z0000000000000001"),
+            (DeliveryType::Bring, "BR000000001NO"),
+        ]
+        .into_iter()
+        .map(|(delivery_type, tracking_number)| Shipment {
+            id: format!("{delivery_type:?}-1"),
+            delivery_type,
+            tracking_number: tracking_number.to_string(),
+            items: vec![],
+            status: ShipmentState::Sent,
+        })
+        .collect();
+        order
+    }
+
+    #[rstest]
+    #[case(DeliveryType::Gls, include_str!("../../fixtures/tracking/gls_delivered.json"))]
+    #[case(DeliveryType::Ups, include_str!("../../fixtures/tracking/ups_delivered.json"))]
+    #[case(DeliveryType::Bring, include_str!("../../fixtures/tracking/bring_delivered.json"))]
+    fn parse_delivered(#[case] carrier: DeliveryType, #[case] payload: &str) {
+        assert_eq!(
+            parse_tracking(carrier, payload, load),
+            Ok(Some(OrderEvent::ShipmentDelivered {
+                order_id: "1234".to_string(),
+                shipment_id: format!("{carrier:?}-1"),
+                time: 1_700_000_000
+            }))
+        );
+    }
+
+    #[rstest]
+    #[case(DeliveryType::Gls, include_str!("../../fixtures/tracking/gls_wrong_address.json"), ReasonCode::WrongAddress)]
+    #[case(DeliveryType::Ups, include_str!("../../fixtures/tracking/ups_lost.json"), ReasonCode::PackageLost)]
+    #[case(DeliveryType::Ups, include_str!("../../fixtures/tracking/ups_customs.json"), ReasonCode::CustomsHold)]
+    #[case(DeliveryType::Bring, include_str!("../../fixtures/tracking/bring_deviation.json"), ReasonCode::WrongAddress)]
+    fn parse_delivery_failed(#[case] carrier: DeliveryType, #[case] payload: &str, #[case] expected: ReasonCode) {
+        let Ok(Some(OrderEvent::ShipmentDeliveryFailed { shipment_id, reason, .. })) = parse_tracking(carrier, payload, load) else {
+            panic!("expected ShipmentDeliveryFailed")
+        };
+        assert_eq!((shipment_id, reason.reason_code), (format!("{carrier:?}-1"), expected));
+    }
+
+    #[test]
+    fn parse_in_transit_and_unknown() {
+        assert_eq!(parse_tracking(DeliveryType::Gls, include_str!("../../fixtures/tracking/gls_in_transit.json"), load), Ok(None));
+        assert_eq!(
+            parse_tracking(DeliveryType::Gls, include_str!("../../fixtures/tracking/gls_unknown.json"), load),
+            Err(TrackingError::UnknownStatusCode { carrier: DeliveryType::Gls, code: "BEAMEDUP".to_string() })
+        );
+        assert_eq!(
+            parse_tracking(DeliveryType::Gls, include_str!("../../fixtures/tracking/gls_delivered.json"), |order_id| Order::new(
+                order_id.to_string()
+            )),
+            Err(TrackingError::UnknownShipment { order_id: "1234".to_string(), tracking_number: "GLS00000000001".to_string() })
+        );
+    }
+}
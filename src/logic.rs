@@ -1,7 +1,637 @@
-use crate::entities::{Action, Order, OrderEvent, OrderEventDiscriminants, State};
-use fsm::{StateMachine, TStateMachine};
+use crate::entities::{
+    Action, AddressBookEntry, AddressUsage, AuthorizationId, Charge, Customer, CustomerConflict, CustomerConflictMode, CustomerEvent,
+    CustomerId, DeliveryResolution, Order, OrderEvent, OrderEventDiscriminants, PaymentAuthorization, PaymentType, PendingAction, Pii,
+    Reason, Settlement, Shipment, ShipmentState, State,
+};
+use fsm::{StateMachine, StateResult, TStateMachine};
+use std::{collections::HashMap, sync::LazyLock};
 // use strum_macros::EnumIter;
 
+/*
+events/state                | Empty      | InProgress           | Payed                | Sent                             | Delivered | PayDiff              | DeliveryFailed                   | Failed | PartiallySent                    | PartiallyDelivered               | Authorized           | Expired | Refunded |
+ItemAdded                   | InProgress | InProgress           | PayDiff              | Failed                           | Failed    | PayDiff              | Failed                           | Failed | Failed                           | Failed                           | Failed               | Failed  | Failed   |
+ItemDeleted                 | Failed     | InProgress           | Payed [RefundDiff]   | Failed                           | Failed    | PayDiff              | Failed                           | Failed | Failed                           | Failed                           | Failed               | Failed  | Failed   |
+OrderPayed                  | Failed     | Payed [PrepareOrder] | Failed               | Failed                           | Failed    | Payed [PrepareOrder] | Failed                           | Failed | Failed                           | Failed                           | Failed               | Failed  | Failed   |
+OrderDetailsAdded           | InProgress | InProgress           | Failed               | Failed                           | Failed    | Failed               | Failed                           | Failed | Failed                           | Failed                           | Failed               | Failed  | Failed   |
+OrderSent                   | Failed     | Failed               | Sent                 | Failed                           | Failed    | Failed               | Sent                             | Failed | Sent                             | Failed                           | Failed               | Failed  | Failed   |
+OrderDelivered              | Failed     | Failed               | Failed               | Delivered                        | Failed    | Failed               | Delivered                        | Failed | Failed                           | Delivered                        | Failed               | Failed  | Failed   |
+OrderDeliveryFailed         | Failed     | Failed               | Failed               | DeliveryFailed [ContactCustomer] | Failed    | Failed               | DeliveryFailed [ContactCustomer] | Failed | DeliveryFailed [ContactCustomer] | DeliveryFailed [ContactCustomer] | Failed               | Failed  | Failed   |
+CustomerAdded               | Empty      | InProgress           | Payed                | Sent                             | Delivered | PayDiff              | DeliveryFailed                   | Failed | PartiallySent                    | PartiallyDelivered               | Authorized           | Expired | Refunded |
+ShipmentSent                | Failed     | Failed               | PartiallySent        | Failed                           | Failed    | Failed               | PartiallySent                    | Failed | PartiallySent                    | PartiallyDelivered               | Failed               | Failed  | Failed   |
+ShipmentDelivered           | Failed     | Failed               | Failed               | PartiallyDelivered               | Failed    | Failed               | PartiallyDelivered               | Failed | PartiallyDelivered               | PartiallyDelivered               | Failed               | Failed  | Failed   |
+ShipmentDeliveryFailed      | Failed     | Failed               | Failed               | DeliveryFailed [ContactCustomer] | Failed    | Failed               | DeliveryFailed [ContactCustomer] | Failed | DeliveryFailed [ContactCustomer] | DeliveryFailed [ContactCustomer] | Failed               | Failed  | Failed   |
+PaymentAuthorized           | Failed     | Authorized [Capture] | Failed               | Failed                           | Failed    | Authorized [Capture] | Failed                           | Failed | Failed                           | Failed                           | Failed               | Failed  | Failed   |
+PaymentCaptured             | Failed     | Failed               | Failed               | Failed                           | Failed    | Failed               | Failed                           | Failed | Failed                           | Failed                           | Payed [PrepareOrder] | Failed  | Failed   |
+PaymentDeclined             | Failed     | InProgress [Pay]     | Failed               | Failed                           | Failed    | PayDiff [Pay]        | Failed                           | Failed | Failed                           | Failed                           | Failed               | Failed  | Failed   |
+PaymentAuthorizationExpired | Failed     | Failed               | Failed               | Failed                           | Failed    | Failed               | Failed                           | Failed | Failed                           | Failed                           | InProgress [Pay]     | Failed  | Failed   |
+PaymentSettled              | Failed     | Failed               | Payed [PrepareOrder] | Sent                             | Delivered | Failed               | DeliveryFailed                   | Failed | PartiallySent                    | PartiallyDelivered               | Failed               | Failed  | Failed   |
+AddressesResolved           | InProgress | InProgress           | Failed               | Failed                           | Failed    | Failed               | Failed                           | Failed | Failed                           | Failed                           | Failed               | Failed  | Failed   |
+OrderExpired                | Expired    | Expired              | Failed               | Failed                           | Failed    | Failed               | Failed                           | Failed | Failed                           | Failed                           | Failed               | Failed  | Failed   |
+ActionCompleted             | Empty      | InProgress           | Payed                | Sent                             | Delivered | PayDiff              | DeliveryFailed                   | Failed | PartiallySent                    | PartiallyDelivered               | Authorized           | Expired | Refunded |
+CustomerResponded           | Empty      | InProgress           | Payed                | Sent                             | Delivered | PayDiff              | DeliveryFailed [Refund]          | Failed | PartiallySent                    | PartiallyDelivered               | Authorized           | Expired | Refunded |
+OrderReshipped              | Failed     | Failed               | Failed               | Failed                           | Failed    | Failed               | Payed [PrepareOrder]             | Failed | Failed                           | Failed                           | Failed               | Failed  | Failed   |
+OrderRefunded               | Failed     | Failed               | Failed               | Failed                           | Failed    | Failed               | Refunded                         | Failed | Failed                           | Failed                           | Failed               | Failed  | Failed   |
+DeliveryEscalated           | Failed     | Failed               | Failed               | Failed                           | Failed    | Failed               | DeliveryFailed [CheckOrder]      | Failed | Failed                           | Failed                           | Failed               | Failed  | Failed   |
+*/
+
+pub static TRANSITIONS: LazyLock<HashMap<(OrderEventDiscriminants, State), StateResult<State, Action>>> = LazyLock::new(|| {
+    let mut map: HashMap<(OrderEventDiscriminants, State), StateResult<State, Action>> = HashMap::new();
+
+    /* ItemAdded */
+    map.insert(
+        (OrderEventDiscriminants::ItemAdded, State::Empty),
+        StateResult { state: State::InProgress, actions: vec![Action::AddItem, Action::DeleteItem] },
+    );
+    map.insert(
+        (OrderEventDiscriminants::ItemAdded, State::InProgress),
+        StateResult { state: State::InProgress, actions: vec![Action::AddItem, Action::DeleteItem] },
+    );
+    map.insert((OrderEventDiscriminants::ItemAdded, State::Payed), StateResult { state: State::PayDiff, actions: vec![Action::Pay] });
+    map.insert((OrderEventDiscriminants::ItemAdded, State::PayDiff), StateResult { state: State::PayDiff, actions: vec![Action::Pay] });
+    map.insert((OrderEventDiscriminants::ItemAdded, State::Sent), StateResult { state: State::Failed, actions: vec![] });
+    map.insert((OrderEventDiscriminants::ItemAdded, State::Delivered), StateResult { state: State::Failed, actions: vec![] });
+    map.insert((OrderEventDiscriminants::ItemAdded, State::DeliveryFailed), StateResult { state: State::Failed, actions: vec![] });
+    map.insert((OrderEventDiscriminants::ItemAdded, State::Failed), StateResult { state: State::Failed, actions: vec![] });
+    map.insert((OrderEventDiscriminants::ItemAdded, State::PartiallySent), StateResult { state: State::Failed, actions: vec![] });
+    map.insert((OrderEventDiscriminants::ItemAdded, State::PartiallyDelivered), StateResult { state: State::Failed, actions: vec![] });
+    map.insert((OrderEventDiscriminants::ItemAdded, State::Authorized), StateResult { state: State::Failed, actions: vec![] });
+    map.insert((OrderEventDiscriminants::ItemAdded, State::Expired), StateResult { state: State::Failed, actions: vec![] });
+    map.insert((OrderEventDiscriminants::ItemAdded, State::Refunded), StateResult { state: State::Failed, actions: vec![] });
+    /* ItemDeleted */
+    map.insert((OrderEventDiscriminants::ItemDeleted, State::Empty), StateResult { state: State::Failed, actions: vec![Action::AddItem] });
+    map.insert(
+        (OrderEventDiscriminants::ItemDeleted, State::InProgress),
+        StateResult { state: State::InProgress, actions: vec![Action::AddItem, Action::DeleteItem] },
+    );
+    map.insert(
+        (OrderEventDiscriminants::ItemDeleted, State::Payed),
+        StateResult { state: State::Payed, actions: vec![Action::RefundDiff] },
+    );
+    map.insert((OrderEventDiscriminants::ItemDeleted, State::PayDiff), StateResult { state: State::PayDiff, actions: vec![Action::Pay] });
+    map.insert((OrderEventDiscriminants::ItemDeleted, State::Sent), StateResult { state: State::Failed, actions: vec![] });
+    map.insert((OrderEventDiscriminants::ItemDeleted, State::Delivered), StateResult { state: State::Failed, actions: vec![] });
+    map.insert((OrderEventDiscriminants::ItemDeleted, State::DeliveryFailed), StateResult { state: State::Failed, actions: vec![] });
+    map.insert((OrderEventDiscriminants::ItemDeleted, State::Failed), StateResult { state: State::Failed, actions: vec![] });
+    map.insert((OrderEventDiscriminants::ItemDeleted, State::PartiallySent), StateResult { state: State::Failed, actions: vec![] });
+    map.insert((OrderEventDiscriminants::ItemDeleted, State::PartiallyDelivered), StateResult { state: State::Failed, actions: vec![] });
+    map.insert((OrderEventDiscriminants::ItemDeleted, State::Authorized), StateResult { state: State::Failed, actions: vec![] });
+    map.insert((OrderEventDiscriminants::ItemDeleted, State::Expired), StateResult { state: State::Failed, actions: vec![] });
+    map.insert((OrderEventDiscriminants::ItemDeleted, State::Refunded), StateResult { state: State::Failed, actions: vec![] });
+    /* OrderPayed */
+    map.insert((OrderEventDiscriminants::OrderPayed, State::Empty), StateResult { state: State::Failed, actions: vec![] });
+    map.insert(
+        (OrderEventDiscriminants::OrderPayed, State::InProgress),
+        StateResult { state: State::Payed, actions: vec![Action::PrepareOrder] },
+    );
+    map.insert((OrderEventDiscriminants::OrderPayed, State::Payed), StateResult { state: State::Failed, actions: vec![] });
+    map.insert(
+        (OrderEventDiscriminants::OrderPayed, State::PayDiff),
+        StateResult { state: State::Payed, actions: vec![Action::PrepareOrder] },
+    );
+    map.insert((OrderEventDiscriminants::OrderPayed, State::Sent), StateResult { state: State::Failed, actions: vec![] });
+    map.insert((OrderEventDiscriminants::OrderPayed, State::Delivered), StateResult { state: State::Failed, actions: vec![] });
+    map.insert((OrderEventDiscriminants::OrderPayed, State::DeliveryFailed), StateResult { state: State::Failed, actions: vec![] });
+    map.insert((OrderEventDiscriminants::OrderPayed, State::Failed), StateResult { state: State::Failed, actions: vec![] });
+    map.insert((OrderEventDiscriminants::OrderPayed, State::PartiallySent), StateResult { state: State::Failed, actions: vec![] });
+    map.insert((OrderEventDiscriminants::OrderPayed, State::PartiallyDelivered), StateResult { state: State::Failed, actions: vec![] });
+    map.insert((OrderEventDiscriminants::OrderPayed, State::Authorized), StateResult { state: State::Failed, actions: vec![] });
+    map.insert((OrderEventDiscriminants::OrderPayed, State::Expired), StateResult { state: State::Failed, actions: vec![] });
+    map.insert((OrderEventDiscriminants::OrderPayed, State::Refunded), StateResult { state: State::Failed, actions: vec![] });
+    /* OrderDetailsAdded */
+    map.insert(
+        (OrderEventDiscriminants::OrderDetailsAdded, State::Empty),
+        StateResult { state: State::InProgress, actions: vec![Action::AddItem, Action::DeleteItem] },
+    );
+    map.insert(
+        (OrderEventDiscriminants::OrderDetailsAdded, State::InProgress),
+        StateResult { state: State::InProgress, actions: vec![Action::AddItem, Action::DeleteItem] },
+    );
+    map.insert((OrderEventDiscriminants::OrderDetailsAdded, State::Payed), StateResult { state: State::Failed, actions: vec![] });
+    map.insert((OrderEventDiscriminants::OrderDetailsAdded, State::PayDiff), StateResult { state: State::Failed, actions: vec![] });
+    map.insert((OrderEventDiscriminants::OrderDetailsAdded, State::Sent), StateResult { state: State::Failed, actions: vec![] });
+    map.insert((OrderEventDiscriminants::OrderDetailsAdded, State::Delivered), StateResult { state: State::Failed, actions: vec![] });
+    map.insert((OrderEventDiscriminants::OrderDetailsAdded, State::DeliveryFailed), StateResult { state: State::Failed, actions: vec![] });
+    map.insert((OrderEventDiscriminants::OrderDetailsAdded, State::Failed), StateResult { state: State::Failed, actions: vec![] });
+    map.insert((OrderEventDiscriminants::OrderDetailsAdded, State::PartiallySent), StateResult { state: State::Failed, actions: vec![] });
+    map.insert(
+        (OrderEventDiscriminants::OrderDetailsAdded, State::PartiallyDelivered),
+        StateResult { state: State::Failed, actions: vec![] },
+    );
+    map.insert((OrderEventDiscriminants::OrderDetailsAdded, State::Authorized), StateResult { state: State::Failed, actions: vec![] });
+    map.insert((OrderEventDiscriminants::OrderDetailsAdded, State::Expired), StateResult { state: State::Failed, actions: vec![] });
+    map.insert((OrderEventDiscriminants::OrderDetailsAdded, State::Refunded), StateResult { state: State::Failed, actions: vec![] });
+    /* OrderSent */
+    map.insert((OrderEventDiscriminants::OrderSent, State::Empty), StateResult { state: State::Failed, actions: vec![] });
+    map.insert((OrderEventDiscriminants::OrderSent, State::InProgress), StateResult { state: State::Failed, actions: vec![] });
+    map.insert((OrderEventDiscriminants::OrderSent, State::Payed), StateResult { state: State::Sent, actions: vec![] });
+    map.insert((OrderEventDiscriminants::OrderSent, State::PayDiff), StateResult { state: State::Failed, actions: vec![] });
+    map.insert((OrderEventDiscriminants::OrderSent, State::Sent), StateResult { state: State::Failed, actions: vec![] });
+    map.insert((OrderEventDiscriminants::OrderSent, State::Delivered), StateResult { state: State::Failed, actions: vec![] });
+    map.insert((OrderEventDiscriminants::OrderSent, State::DeliveryFailed), StateResult { state: State::Sent, actions: vec![] });
+    map.insert((OrderEventDiscriminants::OrderSent, State::Failed), StateResult { state: State::Failed, actions: vec![] });
+    map.insert((OrderEventDiscriminants::OrderSent, State::PartiallySent), StateResult { state: State::Sent, actions: vec![] });
+    map.insert((OrderEventDiscriminants::OrderSent, State::PartiallyDelivered), StateResult { state: State::Failed, actions: vec![] });
+    map.insert((OrderEventDiscriminants::OrderSent, State::Authorized), StateResult { state: State::Failed, actions: vec![] });
+    map.insert((OrderEventDiscriminants::OrderSent, State::Expired), StateResult { state: State::Failed, actions: vec![] });
+    map.insert((OrderEventDiscriminants::OrderSent, State::Refunded), StateResult { state: State::Failed, actions: vec![] });
+    /* OrderDelivered */
+    map.insert((OrderEventDiscriminants::OrderDelivered, State::Empty), StateResult { state: State::Failed, actions: vec![] });
+    map.insert((OrderEventDiscriminants::OrderDelivered, State::InProgress), StateResult { state: State::Failed, actions: vec![] });
+    map.insert((OrderEventDiscriminants::OrderDelivered, State::Payed), StateResult { state: State::Failed, actions: vec![] });
+    map.insert((OrderEventDiscriminants::OrderDelivered, State::PayDiff), StateResult { state: State::Failed, actions: vec![] });
+    map.insert((OrderEventDiscriminants::OrderDelivered, State::Sent), StateResult { state: State::Delivered, actions: vec![] });
+    map.insert((OrderEventDiscriminants::OrderDelivered, State::Delivered), StateResult { state: State::Failed, actions: vec![] });
+    map.insert((OrderEventDiscriminants::OrderDelivered, State::DeliveryFailed), StateResult { state: State::Delivered, actions: vec![] });
+    map.insert((OrderEventDiscriminants::OrderDelivered, State::Failed), StateResult { state: State::Failed, actions: vec![] });
+    map.insert((OrderEventDiscriminants::OrderDelivered, State::PartiallySent), StateResult { state: State::Failed, actions: vec![] });
+    map.insert(
+        (OrderEventDiscriminants::OrderDelivered, State::PartiallyDelivered),
+        StateResult { state: State::Delivered, actions: vec![] },
+    );
+    map.insert((OrderEventDiscriminants::OrderDelivered, State::Authorized), StateResult { state: State::Failed, actions: vec![] });
+    map.insert((OrderEventDiscriminants::OrderDelivered, State::Expired), StateResult { state: State::Failed, actions: vec![] });
+    map.insert((OrderEventDiscriminants::OrderDelivered, State::Refunded), StateResult { state: State::Failed, actions: vec![] });
+    /* OrderDeliveryFailed */
+    map.insert((OrderEventDiscriminants::OrderDeliveryFailed, State::Empty), StateResult { state: State::Failed, actions: vec![] });
+    map.insert((OrderEventDiscriminants::OrderDeliveryFailed, State::InProgress), StateResult { state: State::Failed, actions: vec![] });
+    map.insert((OrderEventDiscriminants::OrderDeliveryFailed, State::Payed), StateResult { state: State::Failed, actions: vec![] });
+    map.insert((OrderEventDiscriminants::OrderDeliveryFailed, State::PayDiff), StateResult { state: State::Failed, actions: vec![] });
+    map.insert(
+        (OrderEventDiscriminants::OrderDeliveryFailed, State::Sent),
+        StateResult { state: State::DeliveryFailed, actions: vec![Action::ContactCustomer] },
+    );
+    map.insert((OrderEventDiscriminants::OrderDeliveryFailed, State::Delivered), StateResult { state: State::Failed, actions: vec![] });
+    map.insert(
+        (OrderEventDiscriminants::OrderDeliveryFailed, State::DeliveryFailed),
+        StateResult { state: State::DeliveryFailed, actions: vec![Action::ContactCustomer] },
+    );
+    map.insert((OrderEventDiscriminants::OrderDeliveryFailed, State::Failed), StateResult { state: State::Failed, actions: vec![] });
+    map.insert(
+        (OrderEventDiscriminants::OrderDeliveryFailed, State::PartiallySent),
+        StateResult { state: State::DeliveryFailed, actions: vec![Action::ContactCustomer] },
+    );
+    map.insert(
+        (OrderEventDiscriminants::OrderDeliveryFailed, State::PartiallyDelivered),
+        StateResult { state: State::DeliveryFailed, actions: vec![Action::ContactCustomer] },
+    );
+    map.insert((OrderEventDiscriminants::OrderDeliveryFailed, State::Authorized), StateResult { state: State::Failed, actions: vec![] });
+    map.insert((OrderEventDiscriminants::OrderDeliveryFailed, State::Expired), StateResult { state: State::Failed, actions: vec![] });
+    map.insert((OrderEventDiscriminants::OrderDeliveryFailed, State::Refunded), StateResult { state: State::Failed, actions: vec![] });
+    /* CustomerAdded */
+    map.insert((OrderEventDiscriminants::CustomerAdded, State::Empty), StateResult { state: State::Empty, actions: vec![] });
+    map.insert((OrderEventDiscriminants::CustomerAdded, State::InProgress), StateResult { state: State::InProgress, actions: vec![] });
+    map.insert((OrderEventDiscriminants::CustomerAdded, State::Payed), StateResult { state: State::Payed, actions: vec![] });
+    map.insert((OrderEventDiscriminants::CustomerAdded, State::PayDiff), StateResult { state: State::PayDiff, actions: vec![] });
+    map.insert((OrderEventDiscriminants::CustomerAdded, State::Sent), StateResult { state: State::Sent, actions: vec![] });
+    map.insert((OrderEventDiscriminants::CustomerAdded, State::Delivered), StateResult { state: State::Delivered, actions: vec![] });
+    map.insert(
+        (OrderEventDiscriminants::CustomerAdded, State::DeliveryFailed),
+        StateResult { state: State::DeliveryFailed, actions: vec![] },
+    );
+    map.insert((OrderEventDiscriminants::CustomerAdded, State::Failed), StateResult { state: State::Failed, actions: vec![] });
+    map.insert(
+        (OrderEventDiscriminants::CustomerAdded, State::PartiallySent),
+        StateResult { state: State::PartiallySent, actions: vec![] },
+    );
+    map.insert(
+        (OrderEventDiscriminants::CustomerAdded, State::PartiallyDelivered),
+        StateResult { state: State::PartiallyDelivered, actions: vec![] },
+    );
+    map.insert((OrderEventDiscriminants::CustomerAdded, State::Authorized), StateResult { state: State::Authorized, actions: vec![] });
+    map.insert((OrderEventDiscriminants::CustomerAdded, State::Expired), StateResult { state: State::Expired, actions: vec![] });
+    map.insert((OrderEventDiscriminants::CustomerAdded, State::Refunded), StateResult { state: State::Refunded, actions: vec![] });
+    /* ShipmentSent */
+    map.insert((OrderEventDiscriminants::ShipmentSent, State::Empty), StateResult { state: State::Failed, actions: vec![] });
+    map.insert((OrderEventDiscriminants::ShipmentSent, State::InProgress), StateResult { state: State::Failed, actions: vec![] });
+    map.insert((OrderEventDiscriminants::ShipmentSent, State::Payed), StateResult { state: State::PartiallySent, actions: vec![] });
+    map.insert((OrderEventDiscriminants::ShipmentSent, State::PayDiff), StateResult { state: State::Failed, actions: vec![] });
+    map.insert((OrderEventDiscriminants::ShipmentSent, State::Sent), StateResult { state: State::Failed, actions: vec![] });
+    map.insert((OrderEventDiscriminants::ShipmentSent, State::Delivered), StateResult { state: State::Failed, actions: vec![] });
+    map.insert(
+        (OrderEventDiscriminants::ShipmentSent, State::DeliveryFailed),
+        StateResult { state: State::PartiallySent, actions: vec![] },
+    );
+    map.insert((OrderEventDiscriminants::ShipmentSent, State::Failed), StateResult { state: State::Failed, actions: vec![] });
+    map.insert((OrderEventDiscriminants::ShipmentSent, State::PartiallySent), StateResult { state: State::PartiallySent, actions: vec![] });
+    map.insert(
+        (OrderEventDiscriminants::ShipmentSent, State::PartiallyDelivered),
+        StateResult { state: State::PartiallyDelivered, actions: vec![] },
+    );
+    map.insert((OrderEventDiscriminants::ShipmentSent, State::Authorized), StateResult { state: State::Failed, actions: vec![] });
+    map.insert((OrderEventDiscriminants::ShipmentSent, State::Expired), StateResult { state: State::Failed, actions: vec![] });
+    map.insert((OrderEventDiscriminants::ShipmentSent, State::Refunded), StateResult { state: State::Failed, actions: vec![] });
+    /* ShipmentDelivered */
+    map.insert((OrderEventDiscriminants::ShipmentDelivered, State::Empty), StateResult { state: State::Failed, actions: vec![] });
+    map.insert((OrderEventDiscriminants::ShipmentDelivered, State::InProgress), StateResult { state: State::Failed, actions: vec![] });
+    map.insert((OrderEventDiscriminants::ShipmentDelivered, State::Payed), StateResult { state: State::Failed, actions: vec![] });
+    map.insert((OrderEventDiscriminants::ShipmentDelivered, State::PayDiff), StateResult { state: State::Failed, actions: vec![] });
+    map.insert(
+        (OrderEventDiscriminants::ShipmentDelivered, State::Sent),
+        StateResult { state: State::PartiallyDelivered, actions: vec![] },
+    );
+    map.insert((OrderEventDiscriminants::ShipmentDelivered, State::Delivered), StateResult { state: State::Failed, actions: vec![] });
+    map.insert(
+        (OrderEventDiscriminants::ShipmentDelivered, State::DeliveryFailed),
+        StateResult { state: State::PartiallyDelivered, actions: vec![] },
+    );
+    map.insert((OrderEventDiscriminants::ShipmentDelivered, State::Failed), StateResult { state: State::Failed, actions: vec![] });
+    map.insert(
+        (OrderEventDiscriminants::ShipmentDelivered, State::PartiallySent),
+        StateResult { state: State::PartiallyDelivered, actions: vec![] },
+    );
+    map.insert(
+        (OrderEventDiscriminants::ShipmentDelivered, State::PartiallyDelivered),
+        StateResult { state: State::PartiallyDelivered, actions: vec![] },
+    );
+    map.insert((OrderEventDiscriminants::ShipmentDelivered, State::Authorized), StateResult { state: State::Failed, actions: vec![] });
+    map.insert((OrderEventDiscriminants::ShipmentDelivered, State::Expired), StateResult { state: State::Failed, actions: vec![] });
+    map.insert((OrderEventDiscriminants::ShipmentDelivered, State::Refunded), StateResult { state: State::Failed, actions: vec![] });
+    /* ShipmentDeliveryFailed */
+    map.insert((OrderEventDiscriminants::ShipmentDeliveryFailed, State::Empty), StateResult { state: State::Failed, actions: vec![] });
+    map.insert((OrderEventDiscriminants::ShipmentDeliveryFailed, State::InProgress), StateResult { state: State::Failed, actions: vec![] });
+    map.insert((OrderEventDiscriminants::ShipmentDeliveryFailed, State::Payed), StateResult { state: State::Failed, actions: vec![] });
+    map.insert(
+        (OrderEventDiscriminants::ShipmentDeliveryFailed, State::Sent),
+        StateResult { state: State::DeliveryFailed, actions: vec![Action::ContactCustomer] },
+    );
+    map.insert((OrderEventDiscriminants::ShipmentDeliveryFailed, State::Delivered), StateResult { state: State::Failed, actions: vec![] });
+    map.insert((OrderEventDiscriminants::ShipmentDeliveryFailed, State::PayDiff), StateResult { state: State::Failed, actions: vec![] });
+    map.insert(
+        (OrderEventDiscriminants::ShipmentDeliveryFailed, State::DeliveryFailed),
+        StateResult { state: State::DeliveryFailed, actions: vec![Action::ContactCustomer] },
+    );
+    map.insert((OrderEventDiscriminants::ShipmentDeliveryFailed, State::Failed), StateResult { state: State::Failed, actions: vec![] });
+    map.insert(
+        (OrderEventDiscriminants::ShipmentDeliveryFailed, State::PartiallySent),
+        StateResult { state: State::DeliveryFailed, actions: vec![Action::ContactCustomer] },
+    );
+    map.insert(
+        (OrderEventDiscriminants::ShipmentDeliveryFailed, State::PartiallyDelivered),
+        StateResult { state: State::DeliveryFailed, actions: vec![Action::ContactCustomer] },
+    );
+    map.insert((OrderEventDiscriminants::ShipmentDeliveryFailed, State::Authorized), StateResult { state: State::Failed, actions: vec![] });
+    map.insert((OrderEventDiscriminants::ShipmentDeliveryFailed, State::Expired), StateResult { state: State::Failed, actions: vec![] });
+    map.insert((OrderEventDiscriminants::ShipmentDeliveryFailed, State::Refunded), StateResult { state: State::Failed, actions: vec![] });
+    /* PaymentAuthorized */
+    map.insert((OrderEventDiscriminants::PaymentAuthorized, State::Empty), StateResult { state: State::Failed, actions: vec![] });
+    map.insert(
+        (OrderEventDiscriminants::PaymentAuthorized, State::InProgress),
+        StateResult { state: State::Authorized, actions: vec![Action::Capture] },
+    );
+    map.insert((OrderEventDiscriminants::PaymentAuthorized, State::Payed), StateResult { state: State::Failed, actions: vec![] });
+    map.insert((OrderEventDiscriminants::PaymentAuthorized, State::Sent), StateResult { state: State::Failed, actions: vec![] });
+    map.insert((OrderEventDiscriminants::PaymentAuthorized, State::Delivered), StateResult { state: State::Failed, actions: vec![] });
+    map.insert(
+        (OrderEventDiscriminants::PaymentAuthorized, State::PayDiff),
+        StateResult { state: State::Authorized, actions: vec![Action::Capture] },
+    );
+    map.insert((OrderEventDiscriminants::PaymentAuthorized, State::DeliveryFailed), StateResult { state: State::Failed, actions: vec![] });
+    map.insert((OrderEventDiscriminants::PaymentAuthorized, State::Failed), StateResult { state: State::Failed, actions: vec![] });
+    map.insert((OrderEventDiscriminants::PaymentAuthorized, State::PartiallySent), StateResult { state: State::Failed, actions: vec![] });
+    map.insert(
+        (OrderEventDiscriminants::PaymentAuthorized, State::PartiallyDelivered),
+        StateResult { state: State::Failed, actions: vec![] },
+    );
+    map.insert((OrderEventDiscriminants::PaymentAuthorized, State::Authorized), StateResult { state: State::Failed, actions: vec![] });
+    map.insert((OrderEventDiscriminants::PaymentAuthorized, State::Expired), StateResult { state: State::Failed, actions: vec![] });
+    map.insert((OrderEventDiscriminants::PaymentAuthorized, State::Refunded), StateResult { state: State::Failed, actions: vec![] });
+    /* PaymentCaptured */
+    map.insert((OrderEventDiscriminants::PaymentCaptured, State::Empty), StateResult { state: State::Failed, actions: vec![] });
+    map.insert((OrderEventDiscriminants::PaymentCaptured, State::InProgress), StateResult { state: State::Failed, actions: vec![] });
+    map.insert((OrderEventDiscriminants::PaymentCaptured, State::Payed), StateResult { state: State::Failed, actions: vec![] });
+    map.insert((OrderEventDiscriminants::PaymentCaptured, State::Sent), StateResult { state: State::Failed, actions: vec![] });
+    map.insert((OrderEventDiscriminants::PaymentCaptured, State::Delivered), StateResult { state: State::Failed, actions: vec![] });
+    map.insert((OrderEventDiscriminants::PaymentCaptured, State::PayDiff), StateResult { state: State::Failed, actions: vec![] });
+    map.insert((OrderEventDiscriminants::PaymentCaptured, State::DeliveryFailed), StateResult { state: State::Failed, actions: vec![] });
+    map.insert((OrderEventDiscriminants::PaymentCaptured, State::Failed), StateResult { state: State::Failed, actions: vec![] });
+    map.insert((OrderEventDiscriminants::PaymentCaptured, State::PartiallySent), StateResult { state: State::Failed, actions: vec![] });
+    map.insert(
+        (OrderEventDiscriminants::PaymentCaptured, State::PartiallyDelivered),
+        StateResult { state: State::Failed, actions: vec![] },
+    );
+    map.insert(
+        (OrderEventDiscriminants::PaymentCaptured, State::Authorized),
+        StateResult { state: State::Payed, actions: vec![Action::PrepareOrder] },
+    );
+    map.insert((OrderEventDiscriminants::PaymentCaptured, State::Expired), StateResult { state: State::Failed, actions: vec![] });
+    map.insert((OrderEventDiscriminants::PaymentCaptured, State::Refunded), StateResult { state: State::Failed, actions: vec![] });
+    /* PaymentDeclined */
+    map.insert((OrderEventDiscriminants::PaymentDeclined, State::Empty), StateResult { state: State::Failed, actions: vec![] });
+    map.insert(
+        (OrderEventDiscriminants::PaymentDeclined, State::InProgress),
+        StateResult { state: State::InProgress, actions: vec![Action::Pay] },
+    );
+    map.insert((OrderEventDiscriminants::PaymentDeclined, State::Payed), StateResult { state: State::Failed, actions: vec![] });
+    map.insert((OrderEventDiscriminants::PaymentDeclined, State::Sent), StateResult { state: State::Failed, actions: vec![] });
+    map.insert((OrderEventDiscriminants::PaymentDeclined, State::Delivered), StateResult { state: State::Failed, actions: vec![] });
+    map.insert(
+        (OrderEventDiscriminants::PaymentDeclined, State::PayDiff),
+        StateResult { state: State::PayDiff, actions: vec![Action::Pay] },
+    );
+    map.insert((OrderEventDiscriminants::PaymentDeclined, State::DeliveryFailed), StateResult { state: State::Failed, actions: vec![] });
+    map.insert((OrderEventDiscriminants::PaymentDeclined, State::Failed), StateResult { state: State::Failed, actions: vec![] });
+    map.insert((OrderEventDiscriminants::PaymentDeclined, State::PartiallySent), StateResult { state: State::Failed, actions: vec![] });
+    map.insert(
+        (OrderEventDiscriminants::PaymentDeclined, State::PartiallyDelivered),
+        StateResult { state: State::Failed, actions: vec![] },
+    );
+    map.insert((OrderEventDiscriminants::PaymentDeclined, State::Authorized), StateResult { state: State::Failed, actions: vec![] });
+    map.insert((OrderEventDiscriminants::PaymentDeclined, State::Expired), StateResult { state: State::Failed, actions: vec![] });
+    map.insert((OrderEventDiscriminants::PaymentDeclined, State::Refunded), StateResult { state: State::Failed, actions: vec![] });
+    /* PaymentAuthorizationExpired */
+    map.insert((OrderEventDiscriminants::PaymentAuthorizationExpired, State::Empty), StateResult { state: State::Failed, actions: vec![] });
+    map.insert(
+        (OrderEventDiscriminants::PaymentAuthorizationExpired, State::InProgress),
+        StateResult { state: State::Failed, actions: vec![] },
+    );
+    map.insert((OrderEventDiscriminants::PaymentAuthorizationExpired, State::Payed), StateResult { state: State::Failed, actions: vec![] });
+    map.insert((OrderEventDiscriminants::PaymentAuthorizationExpired, State::Sent), StateResult { state: State::Failed, actions: vec![] });
+    map.insert(
+        (OrderEventDiscriminants::PaymentAuthorizationExpired, State::Delivered),
+        StateResult { state: State::Failed, actions: vec![] },
+    );
+    map.insert(
+        (OrderEventDiscriminants::PaymentAuthorizationExpired, State::PayDiff),
+        StateResult { state: State::Failed, actions: vec![] },
+    );
+    map.insert(
+        (OrderEventDiscriminants::PaymentAuthorizationExpired, State::DeliveryFailed),
+        StateResult { state: State::Failed, actions: vec![] },
+    );
+    map.insert(
+        (OrderEventDiscriminants::PaymentAuthorizationExpired, State::Failed),
+        StateResult { state: State::Failed, actions: vec![] },
+    );
+    map.insert(
+        (OrderEventDiscriminants::PaymentAuthorizationExpired, State::PartiallySent),
+        StateResult { state: State::Failed, actions: vec![] },
+    );
+    map.insert(
+        (OrderEventDiscriminants::PaymentAuthorizationExpired, State::PartiallyDelivered),
+        StateResult { state: State::Failed, actions: vec![] },
+    );
+    map.insert(
+        (OrderEventDiscriminants::PaymentAuthorizationExpired, State::Authorized),
+        StateResult { state: State::InProgress, actions: vec![Action::Pay] },
+    );
+    map.insert(
+        (OrderEventDiscriminants::PaymentAuthorizationExpired, State::Expired),
+        StateResult { state: State::Failed, actions: vec![] },
+    );
+    map.insert(
+        (OrderEventDiscriminants::PaymentAuthorizationExpired, State::Refunded),
+        StateResult { state: State::Failed, actions: vec![] },
+    );
+    /* PaymentSettled */
+    map.insert((OrderEventDiscriminants::PaymentSettled, State::Empty), StateResult { state: State::Failed, actions: vec![] });
+    map.insert((OrderEventDiscriminants::PaymentSettled, State::InProgress), StateResult { state: State::Failed, actions: vec![] });
+    map.insert(
+        (OrderEventDiscriminants::PaymentSettled, State::Payed),
+        StateResult { state: State::Payed, actions: vec![Action::PrepareOrder] },
+    );
+    map.insert((OrderEventDiscriminants::PaymentSettled, State::Sent), StateResult { state: State::Sent, actions: vec![] });
+    map.insert((OrderEventDiscriminants::PaymentSettled, State::Delivered), StateResult { state: State::Delivered, actions: vec![] });
+    map.insert((OrderEventDiscriminants::PaymentSettled, State::PayDiff), StateResult { state: State::Failed, actions: vec![] });
+    map.insert(
+        (OrderEventDiscriminants::PaymentSettled, State::DeliveryFailed),
+        StateResult { state: State::DeliveryFailed, actions: vec![] },
+    );
+    map.insert((OrderEventDiscriminants::PaymentSettled, State::Failed), StateResult { state: State::Failed, actions: vec![] });
+    map.insert(
+        (OrderEventDiscriminants::PaymentSettled, State::PartiallySent),
+        StateResult { state: State::PartiallySent, actions: vec![] },
+    );
+    map.insert(
+        (OrderEventDiscriminants::PaymentSettled, State::PartiallyDelivered),
+        StateResult { state: State::PartiallyDelivered, actions: vec![] },
+    );
+    map.insert((OrderEventDiscriminants::PaymentSettled, State::Authorized), StateResult { state: State::Failed, actions: vec![] });
+    map.insert((OrderEventDiscriminants::PaymentSettled, State::Expired), StateResult { state: State::Failed, actions: vec![] });
+    map.insert((OrderEventDiscriminants::PaymentSettled, State::Refunded), StateResult { state: State::Failed, actions: vec![] });
+    /* AddressesResolved */
+    map.insert((OrderEventDiscriminants::AddressesResolved, State::Empty), StateResult { state: State::InProgress, actions: vec![] });
+    map.insert((OrderEventDiscriminants::AddressesResolved, State::InProgress), StateResult { state: State::InProgress, actions: vec![] });
+    map.insert((OrderEventDiscriminants::AddressesResolved, State::Payed), StateResult { state: State::Failed, actions: vec![] });
+    map.insert((OrderEventDiscriminants::AddressesResolved, State::Sent), StateResult { state: State::Failed, actions: vec![] });
+    map.insert((OrderEventDiscriminants::AddressesResolved, State::Delivered), StateResult { state: State::Failed, actions: vec![] });
+    map.insert((OrderEventDiscriminants::AddressesResolved, State::PayDiff), StateResult { state: State::Failed, actions: vec![] });
+    map.insert((OrderEventDiscriminants::AddressesResolved, State::DeliveryFailed), StateResult { state: State::Failed, actions: vec![] });
+    map.insert((OrderEventDiscriminants::AddressesResolved, State::Failed), StateResult { state: State::Failed, actions: vec![] });
+    map.insert((OrderEventDiscriminants::AddressesResolved, State::PartiallySent), StateResult { state: State::Failed, actions: vec![] });
+    map.insert(
+        (OrderEventDiscriminants::AddressesResolved, State::PartiallyDelivered),
+        StateResult { state: State::Failed, actions: vec![] },
+    );
+    map.insert((OrderEventDiscriminants::AddressesResolved, State::Authorized), StateResult { state: State::Failed, actions: vec![] });
+    map.insert((OrderEventDiscriminants::AddressesResolved, State::Expired), StateResult { state: State::Failed, actions: vec![] });
+    map.insert((OrderEventDiscriminants::AddressesResolved, State::Refunded), StateResult { state: State::Failed, actions: vec![] });
+    /* OrderExpired */
+    map.insert((OrderEventDiscriminants::OrderExpired, State::Empty), StateResult { state: State::Expired, actions: vec![] });
+    map.insert((OrderEventDiscriminants::OrderExpired, State::InProgress), StateResult { state: State::Expired, actions: vec![] });
+    map.insert((OrderEventDiscriminants::OrderExpired, State::Payed), StateResult { state: State::Failed, actions: vec![] });
+    map.insert((OrderEventDiscriminants::OrderExpired, State::Sent), StateResult { state: State::Failed, actions: vec![] });
+    map.insert((OrderEventDiscriminants::OrderExpired, State::Delivered), StateResult { state: State::Failed, actions: vec![] });
+    map.insert((OrderEventDiscriminants::OrderExpired, State::PayDiff), StateResult { state: State::Failed, actions: vec![] });
+    map.insert((OrderEventDiscriminants::OrderExpired, State::DeliveryFailed), StateResult { state: State::Failed, actions: vec![] });
+    map.insert((OrderEventDiscriminants::OrderExpired, State::Failed), StateResult { state: State::Failed, actions: vec![] });
+    map.insert((OrderEventDiscriminants::OrderExpired, State::PartiallySent), StateResult { state: State::Failed, actions: vec![] });
+    map.insert((OrderEventDiscriminants::OrderExpired, State::PartiallyDelivered), StateResult { state: State::Failed, actions: vec![] });
+    map.insert((OrderEventDiscriminants::OrderExpired, State::Authorized), StateResult { state: State::Failed, actions: vec![] });
+    map.insert((OrderEventDiscriminants::OrderExpired, State::Expired), StateResult { state: State::Failed, actions: vec![] });
+    map.insert((OrderEventDiscriminants::OrderExpired, State::Refunded), StateResult { state: State::Failed, actions: vec![] });
+    /* ActionCompleted */
+    map.insert((OrderEventDiscriminants::ActionCompleted, State::Empty), StateResult { state: State::Empty, actions: vec![] });
+    map.insert((OrderEventDiscriminants::ActionCompleted, State::InProgress), StateResult { state: State::InProgress, actions: vec![] });
+    map.insert((OrderEventDiscriminants::ActionCompleted, State::Payed), StateResult { state: State::Payed, actions: vec![] });
+    map.insert((OrderEventDiscriminants::ActionCompleted, State::Sent), StateResult { state: State::Sent, actions: vec![] });
+    map.insert((OrderEventDiscriminants::ActionCompleted, State::Delivered), StateResult { state: State::Delivered, actions: vec![] });
+    map.insert((OrderEventDiscriminants::ActionCompleted, State::PayDiff), StateResult { state: State::PayDiff, actions: vec![] });
+    map.insert(
+        (OrderEventDiscriminants::ActionCompleted, State::DeliveryFailed),
+        StateResult { state: State::DeliveryFailed, actions: vec![] },
+    );
+    map.insert((OrderEventDiscriminants::ActionCompleted, State::Failed), StateResult { state: State::Failed, actions: vec![] });
+    map.insert(
+        (OrderEventDiscriminants::ActionCompleted, State::PartiallySent),
+        StateResult { state: State::PartiallySent, actions: vec![] },
+    );
+    map.insert(
+        (OrderEventDiscriminants::ActionCompleted, State::PartiallyDelivered),
+        StateResult { state: State::PartiallyDelivered, actions: vec![] },
+    );
+    map.insert((OrderEventDiscriminants::ActionCompleted, State::Authorized), StateResult { state: State::Authorized, actions: vec![] });
+    map.insert((OrderEventDiscriminants::ActionCompleted, State::Expired), StateResult { state: State::Expired, actions: vec![] });
+    map.insert((OrderEventDiscriminants::ActionCompleted, State::Refunded), StateResult { state: State::Refunded, actions: vec![] });
+    /* CustomerResponded */
+    map.insert((OrderEventDiscriminants::CustomerResponded, State::Empty), StateResult { state: State::Empty, actions: vec![] });
+    map.insert((OrderEventDiscriminants::CustomerResponded, State::InProgress), StateResult { state: State::InProgress, actions: vec![] });
+    map.insert((OrderEventDiscriminants::CustomerResponded, State::Payed), StateResult { state: State::Payed, actions: vec![] });
+    map.insert((OrderEventDiscriminants::CustomerResponded, State::Sent), StateResult { state: State::Sent, actions: vec![] });
+    map.insert((OrderEventDiscriminants::CustomerResponded, State::Delivered), StateResult { state: State::Delivered, actions: vec![] });
+    map.insert((OrderEventDiscriminants::CustomerResponded, State::PayDiff), StateResult { state: State::PayDiff, actions: vec![] });
+    map.insert(
+        (OrderEventDiscriminants::CustomerResponded, State::DeliveryFailed),
+        StateResult { state: State::DeliveryFailed, actions: vec![Action::Refund] },
+    );
+    map.insert((OrderEventDiscriminants::CustomerResponded, State::Failed), StateResult { state: State::Failed, actions: vec![] });
+    map.insert(
+        (OrderEventDiscriminants::CustomerResponded, State::PartiallySent),
+        StateResult { state: State::PartiallySent, actions: vec![] },
+    );
+    map.insert(
+        (OrderEventDiscriminants::CustomerResponded, State::PartiallyDelivered),
+        StateResult { state: State::PartiallyDelivered, actions: vec![] },
+    );
+    map.insert((OrderEventDiscriminants::CustomerResponded, State::Authorized), StateResult { state: State::Authorized, actions: vec![] });
+    map.insert((OrderEventDiscriminants::CustomerResponded, State::Expired), StateResult { state: State::Expired, actions: vec![] });
+    map.insert((OrderEventDiscriminants::CustomerResponded, State::Refunded), StateResult { state: State::Refunded, actions: vec![] });
+    /* OrderReshipped */
+    map.insert((OrderEventDiscriminants::OrderReshipped, State::Empty), StateResult { state: State::Failed, actions: vec![] });
+    map.insert((OrderEventDiscriminants::OrderReshipped, State::InProgress), StateResult { state: State::Failed, actions: vec![] });
+    map.insert((OrderEventDiscriminants::OrderReshipped, State::Payed), StateResult { state: State::Failed, actions: vec![] });
+    map.insert((OrderEventDiscriminants::OrderReshipped, State::Sent), StateResult { state: State::Failed, actions: vec![] });
+    map.insert((OrderEventDiscriminants::OrderReshipped, State::Delivered), StateResult { state: State::Failed, actions: vec![] });
+    map.insert((OrderEventDiscriminants::OrderReshipped, State::PayDiff), StateResult { state: State::Failed, actions: vec![] });
+    map.insert(
+        (OrderEventDiscriminants::OrderReshipped, State::DeliveryFailed),
+        StateResult { state: State::Payed, actions: vec![Action::PrepareOrder] },
+    );
+    map.insert((OrderEventDiscriminants::OrderReshipped, State::Failed), StateResult { state: State::Failed, actions: vec![] });
+    map.insert((OrderEventDiscriminants::OrderReshipped, State::PartiallySent), StateResult { state: State::Failed, actions: vec![] });
+    map.insert((OrderEventDiscriminants::OrderReshipped, State::PartiallyDelivered), StateResult { state: State::Failed, actions: vec![] });
+    map.insert((OrderEventDiscriminants::OrderReshipped, State::Authorized), StateResult { state: State::Failed, actions: vec![] });
+    map.insert((OrderEventDiscriminants::OrderReshipped, State::Expired), StateResult { state: State::Failed, actions: vec![] });
+    map.insert((OrderEventDiscriminants::OrderReshipped, State::Refunded), StateResult { state: State::Failed, actions: vec![] });
+    /* OrderRefunded */
+    map.insert((OrderEventDiscriminants::OrderRefunded, State::Empty), StateResult { state: State::Failed, actions: vec![] });
+    map.insert((OrderEventDiscriminants::OrderRefunded, State::InProgress), StateResult { state: State::Failed, actions: vec![] });
+    map.insert((OrderEventDiscriminants::OrderRefunded, State::Payed), StateResult { state: State::Failed, actions: vec![] });
+    map.insert((OrderEventDiscriminants::OrderRefunded, State::Sent), StateResult { state: State::Failed, actions: vec![] });
+    map.insert((OrderEventDiscriminants::OrderRefunded, State::Delivered), StateResult { state: State::Failed, actions: vec![] });
+    map.insert((OrderEventDiscriminants::OrderRefunded, State::PayDiff), StateResult { state: State::Failed, actions: vec![] });
+    map.insert((OrderEventDiscriminants::OrderRefunded, State::DeliveryFailed), StateResult { state: State::Refunded, actions: vec![] });
+    map.insert((OrderEventDiscriminants::OrderRefunded, State::Failed), StateResult { state: State::Failed, actions: vec![] });
+    map.insert((OrderEventDiscriminants::OrderRefunded, State::PartiallySent), StateResult { state: State::Failed, actions: vec![] });
+    map.insert((OrderEventDiscriminants::OrderRefunded, State::PartiallyDelivered), StateResult { state: State::Failed, actions: vec![] });
+    map.insert((OrderEventDiscriminants::OrderRefunded, State::Authorized), StateResult { state: State::Failed, actions: vec![] });
+    map.insert((OrderEventDiscriminants::OrderRefunded, State::Expired), StateResult { state: State::Failed, actions: vec![] });
+    map.insert((OrderEventDiscriminants::OrderRefunded, State::Refunded), StateResult { state: State::Failed, actions: vec![] });
+    /* DeliveryEscalated */
+    map.insert((OrderEventDiscriminants::DeliveryEscalated, State::Empty), StateResult { state: State::Failed, actions: vec![] });
+    map.insert((OrderEventDiscriminants::DeliveryEscalated, State::InProgress), StateResult { state: State::Failed, actions: vec![] });
+    map.insert((OrderEventDiscriminants::DeliveryEscalated, State::Payed), StateResult { state: State::Failed, actions: vec![] });
+    map.insert((OrderEventDiscriminants::DeliveryEscalated, State::Sent), StateResult { state: State::Failed, actions: vec![] });
+    map.insert((OrderEventDiscriminants::DeliveryEscalated, State::Delivered), StateResult { state: State::Failed, actions: vec![] });
+    map.insert((OrderEventDiscriminants::DeliveryEscalated, State::PayDiff), StateResult { state: State::Failed, actions: vec![] });
+    map.insert(
+        (OrderEventDiscriminants::DeliveryEscalated, State::DeliveryFailed),
+        StateResult { state: State::DeliveryFailed, actions: vec![Action::CheckOrder] },
+    );
+    map.insert((OrderEventDiscriminants::DeliveryEscalated, State::Failed), StateResult { state: State::Failed, actions: vec![] });
+    map.insert((OrderEventDiscriminants::DeliveryEscalated, State::PartiallySent), StateResult { state: State::Failed, actions: vec![] });
+    map.insert(
+        (OrderEventDiscriminants::DeliveryEscalated, State::PartiallyDelivered),
+        StateResult { state: State::Failed, actions: vec![] },
+    );
+    map.insert((OrderEventDiscriminants::DeliveryEscalated, State::Authorized), StateResult { state: State::Failed, actions: vec![] });
+    map.insert((OrderEventDiscriminants::DeliveryEscalated, State::Expired), StateResult { state: State::Failed, actions: vec![] });
+    map.insert((OrderEventDiscriminants::DeliveryEscalated, State::Refunded), StateResult { state: State::Failed, actions: vec![] });
+    map
+});
+
+// pub static TRANSITIONS: LazyLock<Vec<Vec<StateResult<State, Action>>>> = LazyLock::new(|| {
+//     vec![
+//         vec![
+//             /* ItemAdded */
+//             StateResult { state: State::InProgress, actions: vec![Action::AddItem, Action::DeleteItem] }, //Empty
+//             StateResult { state: State::InProgress, actions: vec![Action::AddItem, Action::DeleteItem] }, //InProgress
+//             StateResult { state: State::PayDiff, actions: vec![Action::Pay] },                            //Payed
+//             StateResult { state: State::PayDiff, actions: vec![Action::Pay] },                            //PayDiff
+//             StateResult { state: State::Failed, actions: vec![] },                                        //Sent
+//             StateResult { state: State::Failed, actions: vec![] },                                        //Delivered
+//             StateResult { state: State::Failed, actions: vec![] },                                        //DeliveryFailed
+//             StateResult { state: State::Failed, actions: vec![] },                                        //Failed
+//         ],
+//         vec![
+//             /* ItemDeleted */
+//             StateResult { state: State::Failed, actions: vec![Action::AddItem] }, //Empty
+//             StateResult { state: State::InProgress, actions: vec![Action::AddItem, Action::DeleteItem] }, //InProgress
+//             StateResult { state: State::Payed, actions: vec![Action::RefundDiff] }, //Payed
+//             StateResult { state: State::PayDiff, actions: vec![Action::Pay] },    //PayDiff
+//             StateResult { state: State::Failed, actions: vec![] },                //Sent
+//             StateResult { state: State::Failed, actions: vec![] },                //Delivered
+//             StateResult { state: State::Failed, actions: vec![] },                //DeliveryFailed
+//             StateResult { state: State::Failed, actions: vec![] },                //Failed
+//         ],
+//         vec![
+//             /* OrderPayed */
+//             StateResult { state: State::Failed, actions: vec![] }, //Empty
+//             StateResult { state: State::Payed, actions: vec![] },  //InProgress
+//             StateResult { state: State::Failed, actions: vec![] }, //Payed
+//             StateResult { state: State::Payed, actions: vec![] },  //PayDiff
+//             StateResult { state: State::Failed, actions: vec![] }, //Sent
+//             StateResult { state: State::Failed, actions: vec![] }, //Delivered
+//             StateResult { state: State::Failed, actions: vec![] }, //DeliveryFailed
+//             StateResult { state: State::Failed, actions: vec![] }, //Failed
+//         ],
+//         vec![
+//             /* OrderDetailsAdded */
+//             StateResult { state: State::InProgress, actions: vec![Action::AddItem, Action::DeleteItem] }, //Empty
+//             StateResult { state: State::InProgress, actions: vec![Action::AddItem, Action::DeleteItem] }, //InProgress
+//             StateResult { state: State::Failed, actions: vec![] },                                        //Payed
+//             StateResult { state: State::Failed, actions: vec![] },                                        //PayDiff
+//             StateResult { state: State::Failed, actions: vec![] },                                        //Sent
+//             StateResult { state: State::Failed, actions: vec![] },                                        //Delivered
+//             StateResult { state: State::Failed, actions: vec![] },                                        //DeliveryFailed
+//             StateResult { state: State::Failed, actions: vec![] },                                        //Failed
+//         ],
+//         vec![
+//             /* OrderSent */
+//             StateResult { state: State::Failed, actions: vec![] }, //Empty
+//             StateResult { state: State::Failed, actions: vec![] }, //InProgress
+//             StateResult { state: State::Sent, actions: vec![] },   //Payed
+//             StateResult { state: State::Failed, actions: vec![] }, //PayDiff
+//             StateResult { state: State::Failed, actions: vec![] }, //Sent
+//             StateResult { state: State::Failed, actions: vec![] }, //Delivered
+//             StateResult { state: State::Failed, actions: vec![] }, //DeliveryFailed
+//             StateResult { state: State::Failed, actions: vec![] }, //Failed
+//         ],
+//         vec![
+//             /* OrderDelivered */
+//             StateResult { state: State::Failed, actions: vec![] },    //Empty
+//             StateResult { state: State::Failed, actions: vec![] },    //InProgress
+//             StateResult { state: State::Failed, actions: vec![] },    //Payed
+//             StateResult { state: State::Failed, actions: vec![] },    //PayDiff
+//             StateResult { state: State::Delivered, actions: vec![] }, //Sent
+//             StateResult { state: State::Failed, actions: vec![] },    //Delivered
+//             StateResult { state: State::Failed, actions: vec![] },    //DeliveryFailed
+//             StateResult { state: State::Failed, actions: vec![] },    //Failed
+//         ],
+//         vec![
+//             /* OrderDeliveryFailed */
+//             StateResult { state: State::Failed, actions: vec![] }, //Empty
+//             StateResult { state: State::Failed, actions: vec![] }, //InProgress
+//             StateResult { state: State::Failed, actions: vec![] }, //Payed
+//             StateResult { state: State::Failed, actions: vec![] }, //PayDiff
+//             StateResult { state: State::DeliveryFailed, actions: vec![Action::ContactCustomer] }, //Sent
+//             StateResult { state: State::Failed, actions: vec![] }, //Delivered
+//             StateResult { state: State::Failed, actions: vec![] }, //DeliveryFailed
+//             StateResult { state: State::Failed, actions: vec![] }, //Failed
+//         ],
+//         vec![
+//             /* CustomerAdded */
+//             StateResult { state: State::InProgress, actions: vec![Action::AddItem, Action::DeleteItem] }, //Empty
+//             StateResult { state: State::InProgress, actions: vec![Action::AddItem, Action::DeleteItem] }, //InProgress
+//             StateResult { state: State::Failed, actions: vec![] },                                        //Payed
+//             StateResult { state: State::Failed, actions: vec![] },                                        //PayDiff
+//             StateResult { state: State::Failed, actions: vec![] },                                        //Sent
+//             StateResult { state: State::Failed, actions: vec![] },                                        //Delivered
+//             StateResult { state: State::Failed, actions: vec![] },                                        //DeliveryFailed
+//             StateResult { state: State::Failed, actions: vec![] },                                        //Failed
+//         ],
+//     ]
+// });
+
 #[allow(clippy::too_many_lines)]
 pub fn aggregate_order(
     mut events: Vec<OrderEvent>, mut order: Order, machine: &mut StateMachine<State, OrderEventDiscriminants, Action>,
@@ -9,7 +639,7 @@ pub fn aggregate_order(
     if events.is_empty() {
         return order;
     } else if let Some(event) = events.first() {
-        match event {
+        let raised = match event {
             OrderEvent::ItemAdded { id, order_id, time } => {
                 println!("ItemAdded");
                 order.id = order_id.clone();
@@ -17,6 +647,8 @@ pub fn aggregate_order(
                 machine.update_state(OrderEventDiscriminants::ItemAdded);
                 let state = machine.current_state();
                 println!("State {:#?}", state.state);
+                order.status = state.state;
+                state.actions
             }
             OrderEvent::ItemDeleted { id, order_id, time } => {
                 println!("ItemDeleted");
@@ -37,30 +669,31 @@ pub fn aggregate_order(
                         order.status = State::Failed;
                     }
                 }
+                state.actions
             }
             OrderEvent::OrderPayed { order_id, payment_type, amount, time } => {
                 println!("OrderPayed");
                 order.id = order_id.clone();
                 machine.update_state(OrderEventDiscriminants::OrderPayed);
                 let state = machine.current_state();
+                take_payment(&mut order, *payment_type, None, *amount);
                 if state.actions.contains(&Action::PrepareOrder) {
                     order.status = State::Payed;
-                    order.action = Action::PrepareOrder;
                 }
-                order.payment_type = Some(*payment_type);
-                order.amount = *amount;
+                state.actions
             }
             OrderEvent::OrderDetailsAdded { order_id, delivery_type, delivery_address, customer, time } => {
                 println!("OrderDetailsAdded");
                 order.id = order_id.clone();
                 machine.update_state(OrderEventDiscriminants::OrderDetailsAdded);
+                let state = machine.current_state();
+                order.status = state.state;
                 order.delivery_type = Some(*delivery_type);
-                if delivery_address.is_some() {
-                    order.address = delivery_address.clone();
-                }
-                if order.customer.is_none() {
-                    order.customer = Some(customer.clone());
+                if let Some(address) = delivery_address {
+                    order.address = Some(address.reveal());
                 }
+                associate_customer(&mut order, customer);
+                state.actions
             }
             OrderEvent::OrderSent { order_id, time } => {
                 println!("OrderSent");
@@ -68,12 +701,13 @@ pub fn aggregate_order(
                 machine.update_state(OrderEventDiscriminants::OrderSent);
                 let state = machine.current_state();
                 println!("State {:#?}", state.state);
-                if state.state == State::Sent {
+                if state.state == State::Sent && !order.holds_shipping() && reshippable(&order) {
                     order.status = State::Sent;
-                    order.action = Action::None;
+                    order.delivery_failure = None;
+                    state.actions
                 } else {
                     order.status = State::Failed;
-                    order.action = Action::CheckOrder;
+                    vec![Action::CheckOrder]
                 }
             }
             OrderEvent::OrderDelivered { order_id, time } => {
@@ -81,12 +715,13 @@ pub fn aggregate_order(
                 machine.update_state(OrderEventDiscriminants::OrderDelivered);
                 let state = machine.current_state();
                 println!("State {:#?}", state.state);
-                if state.state == State::Delivered {
+                if state.state == State::Delivered && redeliverable(&order) {
                     order.status = State::Delivered;
-                    order.action = Action::None;
+                    order.delivery_failure = None;
+                    state.actions
                 } else {
                     order.status = State::Failed;
-                    order.action = Action::CheckOrder;
+                    vec![Action::CheckOrder]
                 }
             }
             OrderEvent::OrderDeliveryFailed { order_id, reason, time } => {
@@ -94,14 +729,11 @@ pub fn aggregate_order(
                 machine.update_state(OrderEventDiscriminants::OrderDeliveryFailed);
                 let state = machine.current_state();
                 println!("State {:#?}", state.state);
-                if state.actions.contains(&Action::ContactCustomer) {
-                    order.action = Action::ContactCustomer;
-                }
-                if State::DeliveryFailed == state.state {
-                    order.status = State::DeliveryFailed;
+                if State::DeliveryFailed == state.state && redeliverable(&order) {
+                    fail_delivery(&mut order, reason, state.actions)
                 } else {
                     order.status = State::Failed;
-                    order.action = Action::CheckOrder;
+                    vec![Action::CheckOrder]
                 }
             }
             OrderEvent::CustomerAdded { customer, first_name, last_name, address, time } => {
@@ -110,258 +742,513 @@ pub fn aggregate_order(
                 let state = machine.current_state();
                 println!("State {:#?}", state.state);
                 if order.address.is_none() {
-                    order.address = Some(address.clone());
+                    order.address = Some(address.reveal());
                 }
-                order.customer = Some(customer.clone());
+                associate_customer(&mut order, customer);
+                state.actions
             }
-        }
+            OrderEvent::ShipmentSent { order_id, shipment_id, delivery_type, tracking_number, items, .. } => {
+                order.id.clone_from(order_id);
+                machine.update_state(OrderEventDiscriminants::ShipmentSent);
+                let state = machine.current_state();
+                if state.state == State::Failed || order.holds_shipping() || !reshippable(&order) {
+                    order.status = State::Failed;
+                    vec![Action::CheckOrder]
+                } else {
+                    order.shipments.push(Shipment {
+                        id: shipment_id.clone(),
+                        delivery_type: *delivery_type,
+                        tracking_number: tracking_number.clone(),
+                        items: items.clone(),
+                        status: ShipmentState::Sent,
+                    });
+                    if shipment_status(&order) == State::Sent {
+                        machine.update_state(OrderEventDiscriminants::OrderSent);
+                    }
+                    let state = machine.current_state();
+                    order.status = state.state;
+                    order.delivery_failure = None;
+                    state.actions
+                }
+            }
+            OrderEvent::ShipmentDelivered { order_id, shipment_id, .. } => {
+                order.id.clone_from(order_id);
+                machine.update_state(OrderEventDiscriminants::ShipmentDelivered);
+                let state = machine.current_state();
+                let redeliverable = redeliverable(&order);
+                match order.shipments.iter_mut().find(|shipment| &shipment.id == shipment_id) {
+                    // A parcel that failed only turns up later if the carrier retries it by itself.
+                    Some(shipment) if state.state != State::Failed && (shipment.status != ShipmentState::Failed || redeliverable) => {
+                        shipment.status = ShipmentState::Delivered;
+                        if order.delivery_failure.is_some()
+                            && order.shipments.iter().any(|shipment| shipment.status == ShipmentState::Failed)
+                        {
+                            // Another parcel is still undelivered, so the order stays failed until that is resolved.
+                            machine.update_state(OrderEventDiscriminants::ShipmentDeliveryFailed);
+                            order.status = State::DeliveryFailed;
+                            vec![]
+                        } else {
+                            if shipment_status(&order) == State::Delivered {
+                                machine.update_state(OrderEventDiscriminants::OrderDelivered);
+                            }
+                            let state = machine.current_state();
+                            order.status = state.state;
+                            order.delivery_failure = None;
+                            state.actions
+                        }
+                    }
+                    _ => {
+                        order.status = State::Failed;
+                        vec![Action::CheckOrder]
+                    }
+                }
+            }
+            OrderEvent::ShipmentDeliveryFailed { order_id, shipment_id, reason, .. } => {
+                order.id.clone_from(order_id);
+                machine.update_state(OrderEventDiscriminants::ShipmentDeliveryFailed);
+                let state = machine.current_state();
+                let redeliverable = redeliverable(&order);
+                match order.shipments.iter_mut().find(|shipment| &shipment.id == shipment_id) {
+                    Some(shipment)
+                        if state.state == State::DeliveryFailed
+                            && (shipment.status == ShipmentState::Sent || shipment.status == ShipmentState::Failed && redeliverable) =>
+                    {
+                        shipment.status = ShipmentState::Failed;
+                        fail_delivery(&mut order, reason, state.actions)
+                    }
+                    _ => {
+                        order.status = State::Failed;
+                        vec![Action::CheckOrder]
+                    }
+                }
+            }
+            OrderEvent::PaymentAuthorized { order_id, authorization_id, payment_type, amount, expires_at, .. } => {
+                order.id.clone_from(order_id);
+                machine.update_state(OrderEventDiscriminants::PaymentAuthorized);
+                let state = machine.current_state();
+                if state.state == State::Authorized {
+                    order.status = State::Authorized;
+                    order.payment_type = Some(*payment_type);
+                    order.authorization =
+                        Some(PaymentAuthorization { id: authorization_id.clone(), amount: *amount, expires_at: *expires_at });
+                    state.actions
+                } else {
+                    order.status = State::Failed;
+                    vec![Action::CheckOrder]
+                }
+            }
+            OrderEvent::PaymentCaptured { order_id, authorization_id, amount, time } => {
+                order.id.clone_from(order_id);
+                machine.update_state(OrderEventDiscriminants::PaymentCaptured);
+                let state = machine.current_state();
+                let authorized = order.authorization.as_ref().is_some_and(|authorization| {
+                    &authorization.id == authorization_id && *amount <= authorization.amount && *time < authorization.expires_at
+                });
+                if state.state == State::Payed && authorized {
+                    let payment_type = order.payment_type.unwrap_or_default();
+                    take_payment(&mut order, payment_type, Some(authorization_id.clone()), *amount);
+                    order.status = State::Payed;
+                    state.actions
+                } else {
+                    order.status = State::Failed;
+                    vec![Action::CheckOrder]
+                }
+            }
+            OrderEvent::PaymentDeclined { order_id, .. } => {
+                order.id.clone_from(order_id);
+                machine.update_state(OrderEventDiscriminants::PaymentDeclined);
+                let state = machine.current_state();
+                if state.actions.contains(&Action::Pay) {
+                    state.actions
+                } else {
+                    order.status = State::Failed;
+                    vec![Action::CheckOrder]
+                }
+            }
+            OrderEvent::PaymentAuthorizationExpired { order_id, .. } => {
+                order.id.clone_from(order_id);
+                machine.update_state(OrderEventDiscriminants::PaymentAuthorizationExpired);
+                let state = machine.current_state();
+                if state.state == State::InProgress {
+                    order.status = State::InProgress;
+                    order.authorization = None;
+                    state.actions
+                } else {
+                    order.status = State::Failed;
+                    vec![Action::CheckOrder]
+                }
+            }
+            OrderEvent::PaymentSettled { order_id, amount, .. } => {
+                order.id.clone_from(order_id);
+                machine.update_state(OrderEventDiscriminants::PaymentSettled);
+                let state = machine.current_state();
+                if state.state == State::Failed {
+                    order.status = State::Failed;
+                    vec![Action::CheckOrder]
+                } else {
+                    let held = order.holds_shipping();
+                    order.unsettled = order.unsettled.saturating_sub(*amount);
+                    // Only the settlement that lifts the hold releases the order; otherwise it was raised at payment.
+                    if held {
+                        state.actions
+                    } else {
+                        vec![]
+                    }
+                }
+            }
+            OrderEvent::AddressesResolved { order_id, shipping_address, billing_address, .. } => {
+                order.id.clone_from(order_id);
+                machine.update_state(OrderEventDiscriminants::AddressesResolved);
+                let state = machine.current_state();
+                if state.state == State::InProgress {
+                    order.address = Some(shipping_address.reveal());
+                    order.billing_address = Some(billing_address.reveal());
+                    state.actions
+                } else {
+                    order.status = State::Failed;
+                    vec![Action::CheckOrder]
+                }
+            }
+            OrderEvent::OrderExpired { order_id, .. } => {
+                order.id.clone_from(order_id);
+                machine.update_state(OrderEventDiscriminants::OrderExpired);
+                let state = machine.current_state();
+                if state.state == State::Expired {
+                    order.status = State::Expired;
+                    state.actions
+                } else {
+                    order.status = State::Failed;
+                    vec![Action::CheckOrder]
+                }
+            }
+            OrderEvent::ActionCompleted { order_id, .. } => {
+                order.id.clone_from(order_id);
+                machine.update_state(OrderEventDiscriminants::ActionCompleted);
+                machine.current_state().actions
+            }
+            OrderEvent::CustomerResponded { order_id, resolution, .. } => {
+                order.id.clone_from(order_id);
+                machine.update_state(OrderEventDiscriminants::CustomerResponded);
+                // A reshipment is carried out by `OrderReshipped`; only a refund is left to do.
+                let state = machine.current_state();
+                if matches!(resolution, DeliveryResolution::Refund) {
+                    state.actions
+                } else {
+                    vec![]
+                }
+            }
+            OrderEvent::OrderReshipped { order_id, address, .. } => {
+                order.id.clone_from(order_id);
+                machine.update_state(OrderEventDiscriminants::OrderReshipped);
+                let state = machine.current_state();
+                if state.actions.contains(&Action::PrepareOrder) {
+                    order.status = State::Payed;
+                    order.address = Some(address.reveal());
+                    order.delivery_failure = None;
+                    state.actions
+                } else {
+                    order.status = State::Failed;
+                    vec![Action::CheckOrder]
+                }
+            }
+            OrderEvent::OrderRefunded { order_id, amount, .. } => {
+                order.id.clone_from(order_id);
+                machine.update_state(OrderEventDiscriminants::OrderRefunded);
+                let state = machine.current_state();
+                if state.state == State::Refunded {
+                    order.status = State::Refunded;
+                    order.amount = order.amount.saturating_sub(*amount);
+                    state.actions
+                } else {
+                    order.status = State::Failed;
+                    vec![Action::CheckOrder]
+                }
+            }
+            OrderEvent::DeliveryEscalated { order_id, .. } => {
+                order.id.clone_from(order_id);
+                machine.update_state(OrderEventDiscriminants::DeliveryEscalated);
+                let state = machine.current_state();
+                if state.state == State::DeliveryFailed {
+                    state.actions
+                } else {
+                    order.status = State::Failed;
+                    vec![Action::CheckOrder]
+                }
+            }
+        };
+        track_actions(&mut order, event, raised);
     }
     events.remove(0);
     aggregate_order(events, order, machine)
 }
 
+/// Customer precedence: the earliest event naming a customer (`CustomerAdded` or `OrderDetailsAdded`, events
+/// being replayed in time order) decides who the order belongs to. A later event naming a different customer
+/// never changes it; `customer_conflicts` and `check_customer` report it as a conflict instead.
+fn associate_customer(order: &mut Order, customer: &CustomerId) {
+    if order.customer.is_none() {
+        order.customer = Some(customer.clone());
+    }
+}
+
+/// The pending action an event completes, or makes moot, if any.
+fn completed_action(event: &OrderEvent) -> Option<Action> {
+    match event {
+        OrderEvent::OrderPayed { .. } | OrderEvent::PaymentAuthorized { .. } | OrderEvent::OrderExpired { .. } => Some(Action::Pay),
+        OrderEvent::PaymentCaptured { .. } => Some(Action::Capture),
+        OrderEvent::OrderSent { .. } | OrderEvent::ShipmentSent { .. } => Some(Action::PrepareOrder),
+        OrderEvent::OrderRefunded { .. } => Some(Action::Refund),
+        OrderEvent::ActionCompleted { action, .. } => Some(action.clone()),
+        _ => None,
+    }
+}
+
+/// Completes the pending action `event` stands for, then records every action its arm raised that is not
+/// pending yet. `AddItem` and `DeleteItem` only say what the customer may do next and are never pending, and
+/// `PrepareOrder` waits while shipping is held. Leaves `order.action` on the most recently raised pending action.
+fn track_actions(order: &mut Order, event: &OrderEvent, raised: Vec<Action>) {
+    if let Some(completed) = completed_action(event) {
+        if let Some(index) = order.pending_actions.iter().position(|pending| pending.action == completed) {
+            order.pending_actions.remove(index);
+        }
+    }
+    for action in raised {
+        let waiting = match action {
+            Action::None | Action::AddItem | Action::DeleteItem => true,
+            Action::PrepareOrder => order.holds_shipping(),
+            _ => false,
+        };
+        if !waiting && !order.pending_actions.iter().any(|pending| pending.action == action) {
+            order.pending_actions.push(PendingAction { action, origin: event.into(), time: event.time() });
+        }
+    }
+    order.action = order.pending_actions.last().map_or(Action::None, |pending| pending.action.clone());
+}
+
+const fn event_customer(event: &OrderEvent) -> Option<&CustomerId> {
+    match event {
+        OrderEvent::CustomerAdded { customer, .. } | OrderEvent::OrderDetailsAdded { customer, .. } => Some(customer),
+        _ => None,
+    }
+}
+
+/// Checks a new event against the customer an order already belongs to.
+pub fn check_customer(order: &Order, event: &OrderEvent) -> Result<(), CustomerConflict> {
+    match (&order.customer, event_customer(event)) {
+        (Some(kept), Some(customer)) if kept != customer => {
+            Err(CustomerConflict { order_id: order.id.clone(), kept: kept.clone(), rejected: customer.clone(), time: event.time() })
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Lists every customer conflict in an order stream, applying the same precedence as `aggregate_order`.
+pub fn customer_conflicts(order_id: &str, events: &[OrderEvent]) -> Vec<CustomerConflict> {
+    let mut order = Order::new(order_id.to_string());
+    let mut conflicts = vec![];
+    for event in events {
+        match check_customer(&order, event) {
+            Ok(()) => {
+                if let Some(customer) = event_customer(event) {
+                    associate_customer(&mut order, customer);
+                }
+            }
+            Err(conflict) => conflicts.push(conflict),
+        }
+    }
+    conflicts
+}
+
+/// Books money taken on the order. Money that settles later also counts as `unsettled` until `PaymentSettled`.
+fn take_payment(order: &mut Order, payment_type: PaymentType, authorization_id: Option<AuthorizationId>, amount: u32) {
+    order.payment_type = Some(payment_type);
+    order.amount += amount;
+    order.charges.push(Charge { payment_type, authorization_id, amount });
+    if payment_type.settlement() == Settlement::Deferred {
+        order.unsettled += amount;
+    }
+}
+
+/// Whether the order may be sent: always, unless its last delivery failed for a reason that is not solved by
+/// sending the goods again.
+fn reshippable(order: &Order) -> bool {
+    order
+        .delivery_failure
+        .as_ref()
+        .is_none_or(|reason| reason.reason_code.action() == Action::PrepareOrder)
+}
+
+/// Whether a report on another delivery attempt, delivered or failed, is believable: always, unless the last
+/// delivery failed for a reason the carrier does not retry by itself.
+fn redeliverable(order: &Order) -> bool {
+    order.delivery_failure.as_ref().is_none_or(|reason| reason.reason_code.is_retryable())
+}
+
+/// Records a failed delivery. Its reason decides whether the customer is contacted, the goods resent, or the
+/// order checked, so it replaces the `ContactCustomer` the machine raises.
+fn fail_delivery(order: &mut Order, reason: &Reason, actions: Vec<Action>) -> Vec<Action> {
+    order.status = State::DeliveryFailed;
+    order.delivery_failure = Some(reason.clone());
+    actions
+        .into_iter()
+        .map(|action| {
+            if action == Action::ContactCustomer {
+                reason.reason_code.action()
+            } else {
+                action
+            }
+        })
+        .collect()
+}
+
+/// Derives the order state from its shipments. The order is only `Sent` once every item is in a shipment,
+/// and only `Delivered` once every item is shipped and every shipment is delivered. Failed shipments don't
+/// count. The shipment arms of `aggregate_order` move the machine on to `Sent` or `Delivered` when this says so.
+fn shipment_status(order: &Order) -> State {
+    let shipments = || order.shipments.iter().filter(|shipment| shipment.status != ShipmentState::Failed);
+    let all_shipped = order.items.iter().all(|item| shipments().any(|shipment| shipment.items.contains(item)));
+    let all_delivered = shipments().all(|shipment| shipment.status == ShipmentState::Delivered);
+    let any_delivered = shipments().any(|shipment| shipment.status == ShipmentState::Delivered);
+    match (all_shipped, all_delivered, any_delivered) {
+        (true, true, _) => State::Delivered,
+        (_, _, true) => State::PartiallyDelivered,
+        (true, false, false) => State::Sent,
+        (false, _, false) => State::PartiallySent,
+    }
+}
+
+/// Returns the `PaymentAuthorizationExpired` event due for an order whose authorization has run out
+/// before it was captured.
+pub fn authorization_expiry(order: &Order, now: u32) -> Option<OrderEvent> {
+    match &order.authorization {
+        Some(authorization) if order.status == State::Authorized && now >= authorization.expires_at => {
+            Some(OrderEvent::PaymentAuthorizationExpired {
+                order_id: order.id.clone(),
+                authorization_id: authorization.id.clone(),
+                time: now,
+            })
+        }
+        _ => None,
+    }
+}
+
+/// Fixes the addresses an order is shipped to and billed to at checkout. A delivery address given on the
+/// order wins over the customer's default shipping address; billing falls back to the shipping address. The
+/// address book of a deleted customer is not used.
+pub fn resolve_addresses(order: &Order, customer: &Customer, time: u32) -> Option<OrderEvent> {
+    let book = Some(customer).filter(|customer| !customer.deleted);
+    let shipping_address = order.address.as_ref().or_else(|| book.and_then(Customer::default_shipping))?.clone();
+    let billing_address = book.and_then(Customer::default_billing).cloned().unwrap_or_else(|| shipping_address.clone());
+    Some(OrderEvent::AddressesResolved {
+        order_id: order.id.clone(),
+        shipping_address: Pii::Plain(shipping_address),
+        billing_address: Pii::Plain(billing_address),
+        time,
+    })
+}
+
+pub fn aggregate_customer(mut events: Vec<CustomerEvent>, mut customer: Customer) -> Customer {
+    if events.is_empty() {
+        return customer;
+    } else if let Some(event) = events.first() {
+        match event {
+            CustomerEvent::CustomerRegistered { customer: id, first_name, last_name, email, address, .. } => {
+                customer.id.clone_from(id);
+                customer.first_name = first_name.reveal();
+                customer.last_name = last_name.reveal();
+                customer.email = email.reveal();
+                customer.addresses = vec![AddressBookEntry {
+                    name: "home".to_string(),
+                    address: address.reveal(),
+                    default_shipping: true,
+                    default_billing: true,
+                }];
+                customer.deleted = false;
+            }
+            CustomerEvent::CustomerAddressChanged { name, address, .. } => {
+                if let Some(entry) = customer.addresses.iter_mut().find(|entry| &entry.name == name) {
+                    entry.address = address.reveal();
+                } else {
+                    let first = customer.addresses.is_empty();
+                    customer.addresses.push(AddressBookEntry {
+                        name: name.clone(),
+                        address: address.reveal(),
+                        default_shipping: first,
+                        default_billing: first,
+                    });
+                }
+            }
+            CustomerEvent::CustomerAddressRemoved { name, .. } => {
+                customer.addresses.retain(|entry| &entry.name != name);
+                let has_shipping = customer.addresses.iter().any(|entry| entry.default_shipping);
+                let has_billing = customer.addresses.iter().any(|entry| entry.default_billing);
+                if let Some(first) = customer.addresses.first_mut() {
+                    first.default_shipping |= !has_shipping;
+                    first.default_billing |= !has_billing;
+                }
+            }
+            CustomerEvent::CustomerDefaultAddressSet { name, usage, .. } => {
+                if customer.addresses.iter().any(|entry| &entry.name == name) {
+                    for entry in &mut customer.addresses {
+                        let is_default = &entry.name == name;
+                        match usage {
+                            AddressUsage::Shipping => entry.default_shipping = is_default,
+                            AddressUsage::Billing => entry.default_billing = is_default,
+                        }
+                    }
+                }
+            }
+            CustomerEvent::CustomerEmailChanged { email, .. } => {
+                customer.email = email.reveal();
+            }
+            CustomerEvent::CustomerDeleted { .. } => {
+                customer.deleted = true;
+            }
+        }
+    }
+    events.remove(0);
+    aggregate_customer(events, customer)
+}
+
 pub fn add_event(event: OrderEvent, store_fn: fn(OrderEvent) -> Vec<OrderEvent>) -> Vec<OrderEvent> {
     let mut events = store_fn(event);
     events.sort_by(std::cmp::Ord::cmp);
     events
 }
 
+/// Like `add_event`, but checks the event against the order's customer first. `Warn` stores the event anyway
+/// and returns the conflict alongside the events for the caller to report; `Reject` refuses to store it.
+pub fn add_event_checked(
+    event: OrderEvent, order: &Order, mode: CustomerConflictMode, store_fn: fn(OrderEvent) -> Vec<OrderEvent>,
+) -> Result<(Vec<OrderEvent>, Option<CustomerConflict>), CustomerConflict> {
+    match (check_customer(order, &event), mode) {
+        (Err(conflict), CustomerConflictMode::Reject) => Err(conflict),
+        (Err(conflict), CustomerConflictMode::Warn) => Ok((add_event(event, store_fn), Some(conflict))),
+        _ => Ok((add_event(event, store_fn), None)),
+    }
+}
+
+pub fn add_customer_event(event: CustomerEvent, store_fn: fn(CustomerEvent) -> Vec<CustomerEvent>) -> Vec<CustomerEvent> {
+    let mut events = store_fn(event);
+    events.sort_by(std::cmp::Ord::cmp);
+    events
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
     use crate::{
-        entities::{Action, Address, CountryCode, DeliveryType, Order, OrderEvent, PaymentType, Reason, ReasonCode, State},
-        logic::{add_event, aggregate_order},
+        entities::{
+            Action, Address, AddressBookEntry, AddressUsage, CountryCode, Customer, CustomerConflict, CustomerConflictMode, CustomerEvent,
+            DeliveryType, Order, OrderEvent, PaymentType, Pii, Reason, ReasonCode, ShipmentState, State,
+        },
+        logic::{
+            add_customer_event, add_event, add_event_checked, aggregate_customer, aggregate_order, customer_conflicts, resolve_addresses,
+        },
     };
-    use fsm::{StateMachine, StateResult};
-    use std::{collections::HashMap, sync::LazyLock};
+    use fsm::StateMachine;
     use strum::IntoEnumIterator;
 
-    /*
-    events/state        | Empty      | InProgress | Payed                    | Sent                               | Delivered  | PayDiff  | DeliveryFailed | Failed |
-    ItemAdded           | InProgress | InProgress | PayDiff                  | Failed                             | Failed     | PayDiff  | Failed         | Failed |
-    ItemDeleted         | Failed     | InProgress | Payed [RefundDiff]       | Failed                             | Failed     | PayDiff  | Failed         | Failed |
-    OrderPayed          | Failed     | Payed      | Failed                   | Failed                             | Failed     | Payed    | Failed         | Failed |
-    OrderDetailsAdded   | InProgress | InProgress | Failed                   | Failed                             | Failed     | Failed   | Failed         | Failed |
-    OrderSent           | Failed     | Failed     | Sent                     | Failed                             | Failed     | Failed   | Failed         | Failed |
-    OrderDelivered      | Failed     | Failed     | Failed                   | Delivered                          | Failed     | Failed   | Failed         | Failed |
-    OrderDeliveryFailed | Failed     | Failed     | Failed                   | DeliveryFailed [ContactCustomer]   | Failed     | Failed   | Failed         | Failed |
-    CustomerAdded       | InProgress | InProgress | Failed                   | Failed                             | Failed     | Failed   | Failed         | Failed |
-    */
-
-    static TRANSITIONS: LazyLock<HashMap<(OrderEventDiscriminants, State), StateResult<State, Action>>> = LazyLock::new(|| {
-        let mut map: HashMap<(OrderEventDiscriminants, State), StateResult<State, Action>> = HashMap::new();
-
-        /* ItemAdded */
-        map.insert(
-            (OrderEventDiscriminants::ItemAdded, State::Empty),
-            StateResult { state: State::InProgress, actions: vec![Action::AddItem, Action::DeleteItem] },
-        );
-        map.insert(
-            (OrderEventDiscriminants::ItemAdded, State::InProgress),
-            StateResult { state: State::InProgress, actions: vec![Action::AddItem, Action::DeleteItem] },
-        );
-        map.insert((OrderEventDiscriminants::ItemAdded, State::Payed), StateResult { state: State::PayDiff, actions: vec![Action::Pay] });
-        map.insert((OrderEventDiscriminants::ItemAdded, State::PayDiff), StateResult { state: State::PayDiff, actions: vec![Action::Pay] });
-        map.insert((OrderEventDiscriminants::ItemAdded, State::Sent), StateResult { state: State::Failed, actions: vec![] });
-        map.insert((OrderEventDiscriminants::ItemAdded, State::Delivered), StateResult { state: State::Failed, actions: vec![] });
-        map.insert((OrderEventDiscriminants::ItemAdded, State::DeliveryFailed), StateResult { state: State::Failed, actions: vec![] });
-        map.insert((OrderEventDiscriminants::ItemAdded, State::Failed), StateResult { state: State::Failed, actions: vec![] });
-        /* ItemDeleted */
-        map.insert(
-            (OrderEventDiscriminants::ItemDeleted, State::Empty),
-            StateResult { state: State::Failed, actions: vec![Action::AddItem] },
-        );
-        map.insert(
-            (OrderEventDiscriminants::ItemDeleted, State::InProgress),
-            StateResult { state: State::InProgress, actions: vec![Action::AddItem, Action::DeleteItem] },
-        );
-        map.insert(
-            (OrderEventDiscriminants::ItemDeleted, State::Payed),
-            StateResult { state: State::Payed, actions: vec![Action::RefundDiff] },
-        );
-        map.insert(
-            (OrderEventDiscriminants::ItemDeleted, State::PayDiff),
-            StateResult { state: State::PayDiff, actions: vec![Action::Pay] },
-        );
-        map.insert((OrderEventDiscriminants::ItemDeleted, State::Sent), StateResult { state: State::Failed, actions: vec![] });
-        map.insert((OrderEventDiscriminants::ItemDeleted, State::Delivered), StateResult { state: State::Failed, actions: vec![] });
-        map.insert((OrderEventDiscriminants::ItemDeleted, State::DeliveryFailed), StateResult { state: State::Failed, actions: vec![] });
-        map.insert((OrderEventDiscriminants::ItemDeleted, State::Failed), StateResult { state: State::Failed, actions: vec![] });
-        /* OrderPayed */
-        map.insert((OrderEventDiscriminants::OrderPayed, State::Empty), StateResult { state: State::Failed, actions: vec![] });
-        map.insert((OrderEventDiscriminants::OrderPayed, State::InProgress), StateResult { state: State::Payed, actions: vec![] });
-        map.insert((OrderEventDiscriminants::OrderPayed, State::Payed), StateResult { state: State::Failed, actions: vec![] });
-        map.insert((OrderEventDiscriminants::OrderPayed, State::PayDiff), StateResult { state: State::Payed, actions: vec![] });
-        map.insert((OrderEventDiscriminants::OrderPayed, State::Sent), StateResult { state: State::Failed, actions: vec![] });
-        map.insert((OrderEventDiscriminants::OrderPayed, State::Delivered), StateResult { state: State::Failed, actions: vec![] });
-        map.insert((OrderEventDiscriminants::OrderPayed, State::DeliveryFailed), StateResult { state: State::Failed, actions: vec![] });
-        map.insert((OrderEventDiscriminants::OrderPayed, State::Failed), StateResult { state: State::Failed, actions: vec![] });
-        /* OrderDetailsAdded */
-        map.insert(
-            (OrderEventDiscriminants::OrderDetailsAdded, State::Empty),
-            StateResult { state: State::InProgress, actions: vec![Action::AddItem, Action::DeleteItem] },
-        );
-        map.insert(
-            (OrderEventDiscriminants::OrderDetailsAdded, State::InProgress),
-            StateResult { state: State::InProgress, actions: vec![Action::AddItem, Action::DeleteItem] },
-        );
-        map.insert((OrderEventDiscriminants::OrderDetailsAdded, State::Payed), StateResult { state: State::Failed, actions: vec![] });
-        map.insert((OrderEventDiscriminants::OrderDetailsAdded, State::PayDiff), StateResult { state: State::Failed, actions: vec![] });
-        map.insert((OrderEventDiscriminants::OrderDetailsAdded, State::Sent), StateResult { state: State::Failed, actions: vec![] });
-        map.insert((OrderEventDiscriminants::OrderDetailsAdded, State::Delivered), StateResult { state: State::Failed, actions: vec![] });
-        map.insert(
-            (OrderEventDiscriminants::OrderDetailsAdded, State::DeliveryFailed),
-            StateResult { state: State::Failed, actions: vec![] },
-        );
-        map.insert((OrderEventDiscriminants::OrderDetailsAdded, State::Failed), StateResult { state: State::Failed, actions: vec![] });
-        /* OrderSent */
-        map.insert((OrderEventDiscriminants::OrderSent, State::Empty), StateResult { state: State::Failed, actions: vec![] });
-        map.insert((OrderEventDiscriminants::OrderSent, State::InProgress), StateResult { state: State::Failed, actions: vec![] });
-        map.insert((OrderEventDiscriminants::OrderSent, State::Payed), StateResult { state: State::Sent, actions: vec![] });
-        map.insert((OrderEventDiscriminants::OrderSent, State::PayDiff), StateResult { state: State::Failed, actions: vec![] });
-        map.insert((OrderEventDiscriminants::OrderSent, State::Sent), StateResult { state: State::Failed, actions: vec![] });
-        map.insert((OrderEventDiscriminants::OrderSent, State::Delivered), StateResult { state: State::Failed, actions: vec![] });
-        map.insert((OrderEventDiscriminants::OrderSent, State::DeliveryFailed), StateResult { state: State::Failed, actions: vec![] });
-        map.insert((OrderEventDiscriminants::OrderSent, State::Failed), StateResult { state: State::Failed, actions: vec![] });
-        /* OrderDelivered */
-        map.insert((OrderEventDiscriminants::OrderDelivered, State::Empty), StateResult { state: State::Failed, actions: vec![] });
-        map.insert((OrderEventDiscriminants::OrderDelivered, State::InProgress), StateResult { state: State::Failed, actions: vec![] });
-        map.insert((OrderEventDiscriminants::OrderDelivered, State::Payed), StateResult { state: State::Failed, actions: vec![] });
-        map.insert((OrderEventDiscriminants::OrderDelivered, State::PayDiff), StateResult { state: State::Failed, actions: vec![] });
-        map.insert((OrderEventDiscriminants::OrderDelivered, State::Sent), StateResult { state: State::Delivered, actions: vec![] });
-        map.insert((OrderEventDiscriminants::OrderDelivered, State::Delivered), StateResult { state: State::Failed, actions: vec![] });
-        map.insert((OrderEventDiscriminants::OrderDelivered, State::DeliveryFailed), StateResult { state: State::Failed, actions: vec![] });
-        map.insert((OrderEventDiscriminants::OrderDelivered, State::Failed), StateResult { state: State::Failed, actions: vec![] });
-        /* OrderDeliveryFailed */
-        map.insert((OrderEventDiscriminants::OrderDeliveryFailed, State::Empty), StateResult { state: State::Failed, actions: vec![] });
-        map.insert(
-            (OrderEventDiscriminants::OrderDeliveryFailed, State::InProgress),
-            StateResult { state: State::Failed, actions: vec![] },
-        );
-        map.insert((OrderEventDiscriminants::OrderDeliveryFailed, State::Payed), StateResult { state: State::Failed, actions: vec![] });
-        map.insert((OrderEventDiscriminants::OrderDeliveryFailed, State::PayDiff), StateResult { state: State::Failed, actions: vec![] });
-        map.insert(
-            (OrderEventDiscriminants::OrderDeliveryFailed, State::Sent),
-            StateResult { state: State::DeliveryFailed, actions: vec![Action::ContactCustomer] },
-        );
-        map.insert((OrderEventDiscriminants::OrderDeliveryFailed, State::Delivered), StateResult { state: State::Failed, actions: vec![] });
-        map.insert(
-            (OrderEventDiscriminants::OrderDeliveryFailed, State::DeliveryFailed),
-            StateResult { state: State::Failed, actions: vec![] },
-        );
-        map.insert((OrderEventDiscriminants::OrderDeliveryFailed, State::Failed), StateResult { state: State::Failed, actions: vec![] });
-        /* CustomerAdded */
-        map.insert(
-            (OrderEventDiscriminants::CustomerAdded, State::Empty),
-            StateResult { state: State::InProgress, actions: vec![Action::AddItem, Action::DeleteItem] },
-        );
-        map.insert(
-            (OrderEventDiscriminants::CustomerAdded, State::InProgress),
-            StateResult { state: State::InProgress, actions: vec![Action::AddItem, Action::DeleteItem] },
-        );
-        map.insert((OrderEventDiscriminants::CustomerAdded, State::Payed), StateResult { state: State::Failed, actions: vec![] });
-        map.insert((OrderEventDiscriminants::CustomerAdded, State::PayDiff), StateResult { state: State::Failed, actions: vec![] });
-        map.insert((OrderEventDiscriminants::CustomerAdded, State::Sent), StateResult { state: State::Failed, actions: vec![] });
-        map.insert((OrderEventDiscriminants::CustomerAdded, State::Delivered), StateResult { state: State::Failed, actions: vec![] });
-        map.insert((OrderEventDiscriminants::CustomerAdded, State::DeliveryFailed), StateResult { state: State::Failed, actions: vec![] });
-        map.insert((OrderEventDiscriminants::CustomerAdded, State::Failed), StateResult { state: State::Failed, actions: vec![] });
-        map
-    });
-
-    // static TRANSITIONS: LazyLock<Vec<Vec<StateResult<State, Action>>>> = LazyLock::new(|| {
-    //     vec![
-    //         vec![
-    //             /* ItemAdded */
-    //             StateResult { state: State::InProgress, actions: vec![Action::AddItem, Action::DeleteItem] }, //Empty
-    //             StateResult { state: State::InProgress, actions: vec![Action::AddItem, Action::DeleteItem] }, //InProgress
-    //             StateResult { state: State::PayDiff, actions: vec![Action::Pay] },                            //Payed
-    //             StateResult { state: State::PayDiff, actions: vec![Action::Pay] },                            //PayDiff
-    //             StateResult { state: State::Failed, actions: vec![] },                                        //Sent
-    //             StateResult { state: State::Failed, actions: vec![] },                                        //Delivered
-    //             StateResult { state: State::Failed, actions: vec![] },                                        //DeliveryFailed
-    //             StateResult { state: State::Failed, actions: vec![] },                                        //Failed
-    //         ],
-    //         vec![
-    //             /* ItemDeleted */
-    //             StateResult { state: State::Failed, actions: vec![Action::AddItem] }, //Empty
-    //             StateResult { state: State::InProgress, actions: vec![Action::AddItem, Action::DeleteItem] }, //InProgress
-    //             StateResult { state: State::Payed, actions: vec![Action::RefundDiff] }, //Payed
-    //             StateResult { state: State::PayDiff, actions: vec![Action::Pay] },    //PayDiff
-    //             StateResult { state: State::Failed, actions: vec![] },                //Sent
-    //             StateResult { state: State::Failed, actions: vec![] },                //Delivered
-    //             StateResult { state: State::Failed, actions: vec![] },                //DeliveryFailed
-    //             StateResult { state: State::Failed, actions: vec![] },                //Failed
-    //         ],
-    //         vec![
-    //             /* OrderPayed */
-    //             StateResult { state: State::Failed, actions: vec![] }, //Empty
-    //             StateResult { state: State::Payed, actions: vec![] },  //InProgress
-    //             StateResult { state: State::Failed, actions: vec![] }, //Payed
-    //             StateResult { state: State::Payed, actions: vec![] },  //PayDiff
-    //             StateResult { state: State::Failed, actions: vec![] }, //Sent
-    //             StateResult { state: State::Failed, actions: vec![] }, //Delivered
-    //             StateResult { state: State::Failed, actions: vec![] }, //DeliveryFailed
-    //             StateResult { state: State::Failed, actions: vec![] }, //Failed
-    //         ],
-    //         vec![
-    //             /* OrderDetailsAdded */
-    //             StateResult { state: State::InProgress, actions: vec![Action::AddItem, Action::DeleteItem] }, //Empty
-    //             StateResult { state: State::InProgress, actions: vec![Action::AddItem, Action::DeleteItem] }, //InProgress
-    //             StateResult { state: State::Failed, actions: vec![] },                                        //Payed
-    //             StateResult { state: State::Failed, actions: vec![] },                                        //PayDiff
-    //             StateResult { state: State::Failed, actions: vec![] },                                        //Sent
-    //             StateResult { state: State::Failed, actions: vec![] },                                        //Delivered
-    //             StateResult { state: State::Failed, actions: vec![] },                                        //DeliveryFailed
-    //             StateResult { state: State::Failed, actions: vec![] },                                        //Failed
-    //         ],
-    //         vec![
-    //             /* OrderSent */
-    //             StateResult { state: State::Failed, actions: vec![] }, //Empty
-    //             StateResult { state: State::Failed, actions: vec![] }, //InProgress
-    //             StateResult { state: State::Sent, actions: vec![] },   //Payed
-    //             StateResult { state: State::Failed, actions: vec![] }, //PayDiff
-    //             StateResult { state: State::Failed, actions: vec![] }, //Sent
-    //             StateResult { state: State::Failed, actions: vec![] }, //Delivered
-    //             StateResult { state: State::Failed, actions: vec![] }, //DeliveryFailed
-    //             StateResult { state: State::Failed, actions: vec![] }, //Failed
-    //         ],
-    //         vec![
-    //             /* OrderDelivered */
-    //             StateResult { state: State::Failed, actions: vec![] },    //Empty
-    //             StateResult { state: State::Failed, actions: vec![] },    //InProgress
-    //             StateResult { state: State::Failed, actions: vec![] },    //Payed
-    //             StateResult { state: State::Failed, actions: vec![] },    //PayDiff
-    //             StateResult { state: State::Delivered, actions: vec![] }, //Sent
-    //             StateResult { state: State::Failed, actions: vec![] },    //Delivered
-    //             StateResult { state: State::Failed, actions: vec![] },    //DeliveryFailed
-    //             StateResult { state: State::Failed, actions: vec![] },    //Failed
-    //         ],
-    //         vec![
-    //             /* OrderDeliveryFailed */
-    //             StateResult { state: State::Failed, actions: vec![] }, //Empty
-    //             StateResult { state: State::Failed, actions: vec![] }, //InProgress
-    //             StateResult { state: State::Failed, actions: vec![] }, //Payed
-    //             StateResult { state: State::Failed, actions: vec![] }, //PayDiff
-    //             StateResult { state: State::DeliveryFailed, actions: vec![Action::ContactCustomer] }, //Sent
-    //             StateResult { state: State::Failed, actions: vec![] }, //Delivered
-    //             StateResult { state: State::Failed, actions: vec![] }, //DeliveryFailed
-    //             StateResult { state: State::Failed, actions: vec![] }, //Failed
-    //         ],
-    //         vec![
-    //             /* CustomerAdded */
-    //             StateResult { state: State::InProgress, actions: vec![Action::AddItem, Action::DeleteItem] }, //Empty
-    //             StateResult { state: State::InProgress, actions: vec![Action::AddItem, Action::DeleteItem] }, //InProgress
-    //             StateResult { state: State::Failed, actions: vec![] },                                        //Payed
-    //             StateResult { state: State::Failed, actions: vec![] },                                        //PayDiff
-    //             StateResult { state: State::Failed, actions: vec![] },                                        //Sent
-    //             StateResult { state: State::Failed, actions: vec![] },                                        //Delivered
-    //             StateResult { state: State::Failed, actions: vec![] },                                        //DeliveryFailed
-    //             StateResult { state: State::Failed, actions: vec![] },                                        //Failed
-    //         ],
-    //     ]
-    // });
-
     fn store_event_dummy(event: OrderEvent) -> Vec<OrderEvent> {
         let mut events = vec![
             OrderEvent::ItemAdded { id: "1234".to_string(), order_id: "1234".to_string(), time: 1 },
@@ -370,15 +1257,20 @@ mod tests {
             OrderEvent::ItemDeleted { id: "3456".to_string(), order_id: "1234".to_string(), time: 4 },
             OrderEvent::CustomerAdded {
                 customer: "765432".to_string(),
-                first_name: "Steen".to_string(),
-                last_name: "Larsen".to_string(),
-                address: Address { street: "Taagevej", house_number: 43, zip: 4600, country: CountryCode::Dk },
+                first_name: Pii::Plain("Steen".to_string()),
+                last_name: Pii::Plain("Larsen".to_string()),
+                address: Pii::Plain(Address { street: "Taagevej".to_string(), house_number: 43, zip: 4600, country: CountryCode::Dk }),
                 time: 0,
             },
             OrderEvent::OrderDetailsAdded {
                 order_id: "1234".to_string(),
                 delivery_type: DeliveryType::Gls,
-                delivery_address: Some(Address { street: "Karisevej", house_number: 43, zip: 4690, country: CountryCode::Dk }),
+                delivery_address: Some(Pii::Plain(Address {
+                    street: "Karisevej".to_string(),
+                    house_number: 43,
+                    zip: 4690,
+                    country: CountryCode::Dk,
+                })),
                 customer: "54321".to_string(),
                 time: 5,
             },
@@ -389,6 +1281,54 @@ mod tests {
         events
     }
 
+    fn machine() -> StateMachine<State, OrderEventDiscriminants, Action> {
+        StateMachine::new(State::iter().collect(), OrderEventDiscriminants::iter().collect(), TRANSITIONS.to_owned())
+    }
+
+    fn aggregate(events: Vec<OrderEvent>) -> Order {
+        aggregate_order(events, Order::new("1234".to_string()), &mut machine())
+    }
+
+    fn item_added(id: &str, time: u32) -> OrderEvent {
+        OrderEvent::ItemAdded { id: id.to_string(), order_id: "1234".to_string(), time }
+    }
+
+    fn payed(payment_type: PaymentType, amount: u32, time: u32) -> OrderEvent {
+        OrderEvent::OrderPayed { order_id: "1234".to_string(), payment_type, amount, time }
+    }
+
+    fn delivery_failed(reason_code: ReasonCode, time: u32) -> OrderEvent {
+        OrderEvent::OrderDeliveryFailed {
+            order_id: "1234".to_string(),
+            reason: Reason { reason_code, reason_message: String::new() },
+            time,
+        }
+    }
+
+    fn shipment_sent(shipment_id: &str, item: &str, time: u32) -> OrderEvent {
+        OrderEvent::ShipmentSent {
+            order_id: "1234".to_string(),
+            shipment_id: shipment_id.to_string(),
+            delivery_type: DeliveryType::Gls,
+            tracking_number: format!("GLS-{shipment_id}"),
+            items: vec![item.to_string()],
+            time,
+        }
+    }
+
+    fn shipment_delivered(shipment_id: &str, time: u32) -> OrderEvent {
+        OrderEvent::ShipmentDelivered { order_id: "1234".to_string(), shipment_id: shipment_id.to_string(), time }
+    }
+
+    fn shipment_failed(shipment_id: &str, reason_code: ReasonCode, time: u32) -> OrderEvent {
+        OrderEvent::ShipmentDeliveryFailed {
+            order_id: "1234".to_string(),
+            shipment_id: shipment_id.to_string(),
+            reason: Reason { reason_code, reason_message: String::new() },
+            time,
+        }
+    }
+
     #[test]
     fn aggregate_test() {
         let order = Order {
@@ -396,15 +1336,21 @@ mod tests {
             status: State::Delivered,
             payment_type: Some(PaymentType::Visa),
             amount: 345,
+            unsettled: 0,
             delivery_type: Some(DeliveryType::Gls),
             items: vec!["1234".to_string(), "2345".to_string()],
-            address: Some(Address { street: "Karisevej", house_number: 43, zip: 4690, country: CountryCode::Dk }),
+            address: Some(Address { street: "Karisevej".to_string(), house_number: 43, zip: 4690, country: CountryCode::Dk }),
+            billing_address: None,
             customer: Some("765432".to_string()),
+            shipments: vec![],
+            authorization: None,
+            charges: vec![Charge { payment_type: PaymentType::Visa, authorization_id: None, amount: 345 }],
+            delivery_failure: None,
             action: Action::None,
+            pending_actions: vec![],
         };
         let events = add_event(OrderEvent::OrderDelivered { order_id: "1234".to_string(), time: 8 }, store_event_dummy);
-        let mut machine = StateMachine::new(State::iter().collect(), OrderEventDiscriminants::iter().collect(), TRANSITIONS.to_owned());
-        assert_eq!(aggregate_order(events, Order::new("1234".to_string()), &mut machine), order);
+        assert_eq!(aggregate(events), order);
     }
 
     #[test]
@@ -414,11 +1360,18 @@ mod tests {
             status: State::Delivered,
             payment_type: Some(PaymentType::Visa),
             amount: 345,
+            unsettled: 0,
             delivery_type: Some(DeliveryType::Gls),
             items: vec!["1234".to_string(), "2345".to_string()],
-            address: Some(Address { street: "Taagevej", house_number: 43, zip: 4600, country: CountryCode::Dk }),
+            address: Some(Address { street: "Taagevej".to_string(), house_number: 43, zip: 4600, country: CountryCode::Dk }),
+            billing_address: None,
             customer: Some("765432".to_string()),
+            shipments: vec![],
+            authorization: None,
+            charges: vec![Charge { payment_type: PaymentType::Visa, authorization_id: None, amount: 345 }],
+            delivery_failure: None,
             action: Action::None,
+            pending_actions: vec![],
         };
         let events = vec![
             OrderEvent::ItemAdded { id: "1234".to_string(), order_id: "1234".to_string(), time: 1 },
@@ -427,9 +1380,9 @@ mod tests {
             OrderEvent::ItemDeleted { id: "3456".to_string(), order_id: "1234".to_string(), time: 4 },
             OrderEvent::CustomerAdded {
                 customer: "765432".to_string(),
-                first_name: "Steen".to_string(),
-                last_name: "Larsen".to_string(),
-                address: Address { street: "Taagevej", house_number: 43, zip: 4600, country: CountryCode::Dk },
+                first_name: Pii::Plain("Steen".to_string()),
+                last_name: Pii::Plain("Larsen".to_string()),
+                address: Pii::Plain(Address { street: "Taagevej".to_string(), house_number: 43, zip: 4600, country: CountryCode::Dk }),
                 time: 0,
             },
             OrderEvent::OrderDetailsAdded {
@@ -443,8 +1396,7 @@ mod tests {
             OrderEvent::OrderSent { order_id: "1234".to_string(), time: 7 },
             OrderEvent::OrderDelivered { order_id: "1234".to_string(), time: 8 },
         ];
-        let mut machine = StateMachine::new(State::iter().collect(), OrderEventDiscriminants::iter().collect(), TRANSITIONS.to_owned());
-        assert_eq!(aggregate_order(events, Order::new("1234".to_string()), &mut machine), order);
+        assert_eq!(aggregate(events), order);
     }
 
     #[test]
@@ -454,11 +1406,25 @@ mod tests {
             status: State::DeliveryFailed,
             payment_type: Some(PaymentType::Visa),
             amount: 345,
+            unsettled: 0,
             delivery_type: Some(DeliveryType::Gls),
             items: vec!["1234".to_string(), "2345".to_string()],
-            address: Some(Address { street: "Karisevej", house_number: 43, zip: 4690, country: CountryCode::Dk }),
+            address: Some(Address { street: "Karisevej".to_string(), house_number: 43, zip: 4690, country: CountryCode::Dk }),
+            billing_address: None,
             customer: Some("54321".to_string()),
+            shipments: vec![],
+            authorization: None,
+            charges: vec![Charge { payment_type: PaymentType::Visa, authorization_id: None, amount: 345 }],
+            delivery_failure: Some(Reason {
+                reason_code: ReasonCode::PackageLost,
+                reason_message: "Package went into the sea".to_string(),
+            }),
             action: Action::ContactCustomer,
+            pending_actions: vec![PendingAction {
+                action: Action::ContactCustomer,
+                origin: OrderEventDiscriminants::OrderDeliveryFailed,
+                time: 8,
+            }],
         };
         let events = vec![
             OrderEvent::ItemAdded { id: "1234".to_string(), order_id: "1234".to_string(), time: 1 },
@@ -468,7 +1434,12 @@ mod tests {
             OrderEvent::OrderDetailsAdded {
                 order_id: "1234".to_string(),
                 delivery_type: DeliveryType::Gls,
-                delivery_address: Some(Address { street: "Karisevej", house_number: 43, zip: 4690, country: CountryCode::Dk }),
+                delivery_address: Some(Pii::Plain(Address {
+                    street: "Karisevej".to_string(),
+                    house_number: 43,
+                    zip: 4690,
+                    country: CountryCode::Dk,
+                })),
                 customer: "54321".to_string(),
                 time: 5,
             },
@@ -480,7 +1451,341 @@ mod tests {
                 time: 8,
             },
         ];
-        let mut machine = StateMachine::new(State::iter().collect(), OrderEventDiscriminants::iter().collect(), TRANSITIONS.to_owned());
-        assert_eq!(aggregate_order(events, Order::new("1234".to_string()), &mut machine), order);
+        assert_eq!(aggregate(events), order);
+    }
+
+    #[test]
+    fn aggregate_test_partial_shipments() {
+        let mut events = vec![
+            item_added("1234", 1),
+            item_added("2345", 2),
+            payed(PaymentType::Visa, 345, 3),
+            shipment_sent("S1", "1234", 4),
+        ];
+        assert_eq!(aggregate(events.clone()).status, State::PartiallySent);
+
+        events.push(shipment_sent("S2", "2345", 5));
+        assert_eq!(aggregate(events.clone()).status, State::Sent);
+        events.push(shipment_delivered("S1", 6));
+        let order = aggregate(events.clone());
+        assert_eq!(order.status, State::PartiallyDelivered);
+        assert_eq!(order.shipments.len(), 2);
+
+        events.push(shipment_delivered("S2", 7));
+        assert_eq!(aggregate(events).status, State::Delivered);
+    }
+
+    #[test]
+    fn aggregate_test_shipment_delivery_failed() {
+        let mut events = vec![
+            item_added("1234", 1),
+            item_added("2345", 2),
+            payed(PaymentType::Visa, 345, 3),
+            shipment_sent("S1", "1234", 4),
+            shipment_sent("S2", "2345", 5),
+            shipment_failed("S1", ReasonCode::PackageLost, 6),
+        ];
+        let order = aggregate(events.clone());
+        assert_eq!((order.status, order.action), (State::DeliveryFailed, Action::ContactCustomer));
+
+        events.push(shipment_delivered("S2", 7));
+        let order = aggregate(events.clone());
+        assert_eq!((order.status, order.action), (State::DeliveryFailed, Action::ContactCustomer));
+        assert_eq!(
+            order.shipments.iter().map(|shipment| shipment.status).collect::<Vec<_>>(),
+            vec![ShipmentState::Failed, ShipmentState::Delivered]
+        );
+
+        events.push(shipment_delivered("S1", 8));
+        assert_eq!(aggregate(events).status, State::Failed);
+    }
+
+    #[test]
+    fn aggregate_test_fail_delivery_action_by_reason() {
+        for (reason_code, action) in [
+            (ReasonCode::Damaged, Action::PrepareOrder),
+            (ReasonCode::RecipientAbsent, Action::None),
+            (ReasonCode::CustomsHold, Action::CheckOrder),
+            (ReasonCode::Refused, Action::ContactCustomer),
+        ] {
+            let order = aggregate(vec![
+                item_added("1234", 1),
+                payed(PaymentType::Visa, 345, 2),
+                OrderEvent::OrderSent { order_id: "1234".to_string(), time: 3 },
+                delivery_failed(reason_code, 4),
+            ]);
+            assert_eq!((order.status, order.action), (State::DeliveryFailed, action));
+        }
+    }
+
+    #[test]
+    fn aggregate_test_delivery_after_failure() {
+        let failed = |reason_code| {
+            vec![
+                item_added("1234", 1),
+                payed(PaymentType::Visa, 345, 2),
+                OrderEvent::OrderSent { order_id: "1234".to_string(), time: 3 },
+                delivery_failed(reason_code, 4),
+            ]
+        };
+        for (reason_code, status) in [
+            (ReasonCode::RecipientAbsent, State::Delivered),
+            (ReasonCode::PackageLost, State::Failed),
+        ] {
+            let mut events = failed(reason_code);
+            events.push(OrderEvent::OrderDelivered { order_id: "1234".to_string(), time: 5 });
+            assert_eq!(aggregate(events).status, status);
+        }
+
+        let mut events = failed(ReasonCode::Damaged);
+        events.push(shipment_sent("S2", "1234", 5));
+        let order = aggregate(events.clone());
+        assert_eq!((order.status, order.action, order.delivery_failure), (State::Sent, Action::None, None));
+
+        events.push(shipment_delivered("S2", 6));
+        assert_eq!(aggregate(events).status, State::Delivered);
+
+        let mut events = failed(ReasonCode::WrongAddress);
+        events.push(OrderEvent::OrderSent { order_id: "1234".to_string(), time: 5 });
+        assert_eq!(aggregate(events).status, State::Failed);
+    }
+
+    #[test]
+    fn aggregate_test_repeated_delivery_failure() {
+        let mut events = vec![
+            item_added("1234", 1),
+            payed(PaymentType::Visa, 345, 2),
+            OrderEvent::OrderSent { order_id: "1234".to_string(), time: 3 },
+            delivery_failed(ReasonCode::RecipientAbsent, 4),
+            delivery_failed(ReasonCode::RecipientAbsent, 5),
+        ];
+        let order = aggregate(events.clone());
+        assert_eq!((order.status, order.action), (State::DeliveryFailed, Action::None));
+
+        events.push(delivery_failed(ReasonCode::PackageLost, 6));
+        let order = aggregate(events.clone());
+        assert_eq!((order.status, order.action), (State::DeliveryFailed, Action::ContactCustomer));
+
+        events.push(delivery_failed(ReasonCode::PackageLost, 7));
+        let order = aggregate(events);
+        assert_eq!((order.status, order.action), (State::Failed, Action::CheckOrder));
+    }
+
+    #[test]
+    fn aggregate_test_pay_difference() {
+        let order = aggregate(vec![
+            item_added("1234", 1),
+            payed(PaymentType::Visa, 300, 2),
+            item_added("2345", 3),
+            payed(PaymentType::Visa, 45, 4),
+        ]);
+        assert_eq!((order.status, order.amount), (State::Payed, 345));
+    }
+
+    #[test]
+    fn aggregate_test_bank_transfer_holds_shipping() {
+        let mut events = vec![item_added("1234", 1), payed(PaymentType::BankTransfer, 345, 2)];
+        let order = aggregate(events.clone());
+        assert_eq!((order.status, order.action, order.unsettled), (State::Payed, Action::None, 345));
+
+        let mut unsettled = events.clone();
+        unsettled.push(OrderEvent::OrderSent { order_id: "1234".to_string(), time: 3 });
+        assert_eq!(aggregate(unsettled).status, State::Failed);
+
+        events.push(OrderEvent::PaymentSettled { order_id: "1234".to_string(), amount: 345, time: 3 });
+        events.push(OrderEvent::OrderSent { order_id: "1234".to_string(), time: 4 });
+        assert_eq!(aggregate(events).status, State::Sent);
+    }
+
+    #[test]
+    fn aggregate_test_deferred_settlement() {
+        let mut events = vec![
+            item_added("1234", 1),
+            payed(PaymentType::KlarnaInvoice, 345, 2),
+            OrderEvent::OrderSent { order_id: "1234".to_string(), time: 3 },
+        ];
+        let order = aggregate(events.clone());
+        assert_eq!((order.status, order.unsettled), (State::Sent, 345));
+
+        events.push(OrderEvent::PaymentSettled { order_id: "1234".to_string(), amount: 345, time: 4 });
+        let order = aggregate(events);
+        assert_eq!((order.status, order.unsettled), (State::Sent, 0));
+
+        let order = aggregate(vec![
+            item_added("1234", 1),
+            OrderEvent::PaymentAuthorized {
+                order_id: "1234".to_string(),
+                authorization_id: "AUTH-1".to_string(),
+                payment_type: PaymentType::BankTransfer,
+                amount: 345,
+                expires_at: 100,
+                time: 2,
+            },
+            OrderEvent::PaymentCaptured { order_id: "1234".to_string(), authorization_id: "AUTH-1".to_string(), amount: 345, time: 3 },
+        ]);
+        assert_eq!((order.status, order.action, order.unsettled), (State::Payed, Action::None, 345));
+    }
+
+    #[test]
+    fn aggregate_test_pending_actions() {
+        let mut events = vec![
+            item_added("1234", 1),
+            payed(PaymentType::Visa, 345, 2),
+            OrderEvent::OrderSent { order_id: "1234".to_string(), time: 3 },
+            delivery_failed(ReasonCode::Damaged, 4),
+            OrderEvent::PaymentDeclined {
+                order_id: "1234".to_string(),
+                payment_type: PaymentType::Visa,
+                reason: "duplicate".to_string(),
+                time: 5,
+            },
+        ];
+        let order = aggregate(events.clone());
+        assert_eq!(
+            order.pending_actions,
+            vec![
+                PendingAction { action: Action::PrepareOrder, origin: OrderEventDiscriminants::OrderDeliveryFailed, time: 4 },
+                PendingAction { action: Action::CheckOrder, origin: OrderEventDiscriminants::PaymentDeclined, time: 5 },
+            ]
+        );
+        assert_eq!(order.action, Action::CheckOrder);
+
+        events.push(OrderEvent::ActionCompleted { order_id: "1234".to_string(), action: Action::CheckOrder, time: 6 });
+        let order = aggregate(events.clone());
+        assert_eq!(order.action, Action::PrepareOrder);
+        assert_eq!(order.pending_actions.len(), 1);
+
+        events.push(OrderEvent::ActionCompleted { order_id: "1234".to_string(), action: Action::PrepareOrder, time: 7 });
+        let order = aggregate(events);
+        assert_eq!(order.action, Action::None);
+        assert!(order.pending_actions.is_empty());
+    }
+
+    #[test]
+    fn aggregate_test_actions_from_state() {
+        let events = vec![
+            item_added("1", 1),
+            item_added("2", 2),
+            payed(PaymentType::BankTransfer, 345, 3),
+            OrderEvent::ItemDeleted { id: "2".to_string(), order_id: "1234".to_string(), time: 4 },
+        ];
+        let mut machine = machine();
+        let order = aggregate_order(events, Order::new("1234".to_string()), &mut machine);
+        assert_eq!(
+            order.pending_actions,
+            vec![PendingAction { action: Action::RefundDiff, origin: OrderEventDiscriminants::ItemDeleted, time: 4 }]
+        );
+
+        let settled = vec![OrderEvent::PaymentSettled { order_id: "1234".to_string(), amount: 345, time: 5 }];
+        let order = aggregate_order(settled, order, &mut machine);
+        assert_eq!(order.action, Action::PrepareOrder);
+        assert_eq!(order.pending_actions.len(), 2);
+    }
+
+    #[test]
+    fn aggregate_customer_test() {
+        let home = Address { street: "Taagevej".to_string(), house_number: 43, zip: 4600, country: CountryCode::Dk };
+        let work = Address { street: "Karisevej".to_string(), house_number: 43, zip: 4690, country: CountryCode::Dk };
+        let store_customer_dummy = |event: CustomerEvent| -> Vec<CustomerEvent> {
+            vec![
+                CustomerEvent::CustomerEmailChanged {
+                    customer: "765432".to_string(),
+                    email: Pii::Plain("steen@example.com".to_string()),
+                    time: 2,
+                },
+                CustomerEvent::CustomerRegistered {
+                    customer: "765432".to_string(),
+                    first_name: Pii::Plain("Steen".to_string()),
+                    last_name: Pii::Plain("Larsen".to_string()),
+                    email: Pii::Plain("steen@example.dk".to_string()),
+                    address: Pii::Plain(Address { street: "Taagevej".to_string(), house_number: 43, zip: 4600, country: CountryCode::Dk }),
+                    time: 1,
+                },
+                CustomerEvent::CustomerAddressChanged {
+                    customer: "765432".to_string(),
+                    name: "work".to_string(),
+                    address: Pii::Plain(Address { street: "Karisevej".to_string(), house_number: 43, zip: 4690, country: CountryCode::Dk }),
+                    time: 3,
+                },
+                event,
+            ]
+        };
+        let events = add_customer_event(
+            CustomerEvent::CustomerDefaultAddressSet {
+                customer: "765432".to_string(),
+                name: "work".to_string(),
+                usage: AddressUsage::Shipping,
+                time: 4,
+            },
+            store_customer_dummy,
+        );
+        let customer = aggregate_customer(events.clone(), Customer::new("765432".to_string()));
+        assert_eq!(customer.email, "steen@example.com");
+        assert_eq!(customer.addresses.len(), 2);
+        assert_eq!(customer.default_shipping(), Some(&work));
+        assert_eq!(customer.default_billing(), Some(&home));
+        assert!(!customer.deleted);
+
+        let mut removed = events.clone();
+        removed.push(CustomerEvent::CustomerAddressRemoved { customer: "765432".to_string(), name: "work".to_string(), time: 5 });
+        assert_eq!(aggregate_customer(removed, Customer::new("765432".to_string())).default_shipping(), Some(&home));
+
+        let mut events = events;
+        events.push(CustomerEvent::CustomerDeleted { customer: "765432".to_string(), time: 5 });
+        assert!(aggregate_customer(events, Customer::new("765432".to_string())).deleted);
+    }
+
+    #[test]
+    fn resolve_addresses_test() {
+        let home = Address { street: "Taagevej".to_string(), house_number: 43, zip: 4600, country: CountryCode::Dk };
+        let work = Address { street: "Karisevej".to_string(), house_number: 43, zip: 4690, country: CountryCode::Dk };
+        let mut customer = Customer::new("765432".to_string());
+        customer.addresses = vec![
+            AddressBookEntry { name: "home".to_string(), address: home.clone(), default_shipping: false, default_billing: true },
+            AddressBookEntry { name: "work".to_string(), address: work.clone(), default_shipping: true, default_billing: false },
+        ];
+        let mut events = vec![
+            OrderEvent::ItemAdded { id: "1234".to_string(), order_id: "1234".to_string(), time: 1 },
+            OrderEvent::OrderDetailsAdded {
+                order_id: "1234".to_string(),
+                delivery_type: DeliveryType::Gls,
+                delivery_address: None,
+                customer: "765432".to_string(),
+                time: 2,
+            },
+        ];
+        let order = aggregate(events.clone());
+        let deleted = Customer { deleted: true, ..customer.clone() };
+        assert_eq!(resolve_addresses(&order, &deleted, 3), None);
+        events.extend(resolve_addresses(&order, &customer, 3));
+
+        let order = aggregate(events);
+        assert_eq!(order.address, Some(work));
+        assert_eq!(order.billing_address, Some(home));
+    }
+
+    #[test]
+    fn customer_conflict_test() {
+        let events = add_event(OrderEvent::OrderDelivered { order_id: "1234".to_string(), time: 8 }, store_event_dummy);
+        assert_eq!(
+            customer_conflicts("1234", &events),
+            vec![CustomerConflict { order_id: "1234".to_string(), kept: "765432".to_string(), rejected: "54321".to_string(), time: 5 }]
+        );
+
+        let order = aggregate(events);
+        let details = OrderEvent::OrderDetailsAdded {
+            order_id: "1234".to_string(),
+            delivery_type: DeliveryType::Ups,
+            delivery_address: None,
+            customer: "54321".to_string(),
+            time: 9,
+        };
+        let conflict =
+            CustomerConflict { order_id: "1234".to_string(), kept: "765432".to_string(), rejected: "54321".to_string(), time: 9 };
+        assert_eq!(add_event_checked(details.clone(), &order, CustomerConflictMode::Reject, store_event_dummy), Err(conflict.clone()));
+        let (_, warned) = add_event_checked(details.clone(), &order, CustomerConflictMode::Warn, store_event_dummy).expect("stored");
+        assert_eq!(warned, Some(conflict));
+        let (_, allowed) = add_event_checked(details, &order, CustomerConflictMode::Allow, store_event_dummy).expect("stored");
+        assert_eq!(allowed, None);
     }
 }